@@ -1,81 +1,135 @@
 use image::DynamicImage;
-use once_cell::sync::Lazy;
-use rand::seq::SliceRandom;
 use std::{
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
     time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Context, Result};
 use log::*;
 
-use calloop::EventLoop;
+use calloop::{EventLoop, LoopHandle, RegistrationToken};
 use client::{
+    delegate_noop,
     globals::registry_queue_init,
     protocol::{
         wl_output::{self},
         wl_surface,
     },
-    Connection, QueueHandle,
+    Connection, Dispatch, QueueHandle,
 };
 use smithay_client_toolkit::{
-    compositor::{CompositorHandler, CompositorState, Region},
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry,
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
     output::{OutputHandler, OutputState},
     reexports::{
         calloop::{
             self,
+            channel::{self, Sender},
+            ping::{self, Ping},
             timer::{TimeoutAction, Timer},
         },
         calloop_wayland_source::WaylandSource,
         client,
+        protocols::wp::{
+            fractional_scale::v1::client::{
+                wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
+            },
+            presentation_time::client::{wp_presentation, wp_presentation_feedback},
+            viewporter::client::{wp_viewport, wp_viewporter},
+        },
     },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     shell::{
-        wlr_layer::{Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface},
+        wlr_layer::{LayerShell, LayerShellHandler},
         WaylandSurface,
     },
+    shm::{Shm, ShmHandler},
 };
 
-const FPS: f32 = 60.0;
-static FRAMETIME: Lazy<Duration> = Lazy::new(|| Duration::from_secs_f32(1.0 / FPS));
-const MIN_FPS: f32 = 5.0;
-static MAX_FRAMETIME: Lazy<Duration> = Lazy::new(|| Duration::from_secs_f32(1.0 / MIN_FPS));
-
 use crate::{
-    cli,
-    render::{self, Animation},
+    config::{self, Config, OutputSettings, Overrides},
+    frame_clock,
+    image_loader::{self, load_path, ImageLoader, Target},
+    ipc::{self, OutputStatus, Request, Response},
+    render::{animation::ShaderLibrary, transform},
+    wallpaper::{self, Prefetch, Protocols, RendererChoice, Wallpaper},
 };
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum AppEvent {
-    Draw,
-}
+/// Closes of the surface of an output within this time count towards giving up on it.
+const CLOSE_WINDOW: Duration = Duration::from_secs(60);
+/// Number of closes within [`CLOSE_WINDOW`] after which a surface is not recreated anymore.
+const MAX_CLOSES: usize = 5;
+/// Time until drawing a frame that failed is tried again.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub struct App {
     registry_state: RegistryState,
     output_state: OutputState,
     compositor_state: CompositorState,
     layer_shell: LayerShell,
+    shm: Shm,
+    protocols: Protocols,
+    /// Clock of the presentation timestamps, announced after binding the presentation global
+    presentation_clock: Option<u32>,
 
-    animation: Box<dyn Animation>,
-
-    // drop ctx after animation
-    ctx: render::Context,
-    // Connection and Layer needs to be dropped after ctx
+    // Wallpapers need to be dropped before conn
+    wallpapers: Vec<Wallpaper>,
+    /// Outputs whose first image is being decoded
+    pending: Vec<wl_output::WlOutput>,
+    /// When the compositor recently closed the surface of each output
+    closes: HashMap<wl_output::WlOutput, Vec<Instant>>,
+    /// Timers recreating the surfaces of outputs after the compositor closed them
+    retries: HashMap<wl_output::WlOutput, RegistrationToken>,
     conn: Connection,
-    layer: LayerSurface,
+    qh: QueueHandle<App>,
+    loop_handle: LoopHandle<'static, App>,
+
+    config: Config,
+    config_path: PathBuf,
+    overrides: Overrides,
+    loader: Arc<Mutex<ImageLoader>>,
+    library: Rc<ShaderLibrary>,
+    decoded: Sender<Decoded>,
+    /// Wakes up the event loop, see [`RendererChoice::Software`]
+    wake: Ping,
+    paused: bool,
+    /// Timer waking up the event loop to retry frames that could not be drawn
+    retry_timer: Option<RegistrationToken>,
+}
 
-    img_dir: PathBuf,
-    interval: Duration,
-    configured: bool,
-    frame_timer: FrameTimer,
+/// An image decoded on the worker pool for an output.
+struct Decoded {
+    output: wl_output::WlOutput,
+    purpose: Purpose,
+    result: Result<(PathBuf, DynamicImage)>,
+}
+
+enum Purpose {
+    /// The first image of a new wallpaper
+    Create,
+    Prefetch,
+    /// The image `offset` entries away from the current one in the history, shown if it is still
+    /// there once decoded
+    History {
+        path: PathBuf,
+        offset: isize,
+    },
+    /// The current image, to rebuild the animation with
+    Rebuild(PathBuf),
+    /// An image set through IPC
+    Set,
 }
 
 impl App {
-    pub fn run() -> Result<()> {
-        let (img_dir, interval) = cli::Cli::parse_and_validate()?;
+    pub fn run(config_path: PathBuf, overrides: Overrides) -> Result<()> {
+        let config = Config::load(&config_path, &overrides)?;
+        let loader = ImageLoader::new(&config)?;
+        let library = ShaderLibrary::load(&config::transitions_dir(&config_path));
+        library.check(config.transition_kinds())?;
 
         let conn =
             Connection::connect_to_env().context("Failed to get connection to wayland server")?;
@@ -87,86 +141,70 @@ impl App {
             CompositorState::bind(&globals, &qh).expect("Compositor not available");
         let output_state = OutputState::new(&globals, &qh);
         let layer_shell = LayerShell::bind(&globals, &qh).expect("Layer shell not available");
-        let surface = compositor_state.create_surface(&qh);
-
-        let layer = layer_shell.create_layer_surface(
-            &qh,
-            surface,
-            Layer::Background,
-            Some("wallpaper"),
-            None,
-        );
-        layer.set_anchor(Anchor::all());
-        layer.set_size(0, 0);
-        layer.set_exclusive_zone(-1);
-
-        match Region::new(&compositor_state) {
-            Ok(region) => {
-                layer.set_input_region(Some(region.wl_region()));
-                region.wl_region().destroy();
-            }
-            Err(e) => {
-                warn!("Failed to set input region, background may not have cursor: {e}");
-            }
+        let shm = Shm::bind(&globals, &qh).expect("Shared memory not available");
+        let protocols = Protocols {
+            presentation: globals.bind(&qh, 1..=1, ()).ok(),
+            viewporter: globals.bind(&qh, 1..=1, ()).ok(),
+            fractional_scale: globals.bind(&qh, 1..=1, ()).ok(),
+        };
+        if protocols.presentation.is_none() {
+            info!("Presentation time not available, animations are timed by when frames are drawn");
+        }
+        if protocols.viewporter.is_none() || protocols.fractional_scale.is_none() {
+            info!("Fractional scaling not available, rendering at integer scales");
         }
-
-        layer.commit();
-
-        let ctx = pollster::block_on(render::Context::new(&conn, &layer, (256, 256)));
-        let animation = Box::new(crate::render::animation::Fade::new(
-            &Self::load_random_img(&img_dir).unwrap(),
-            &Self::load_random_img(&img_dir).unwrap(),
-            Duration::from_secs(8),
-            &ctx,
-        ));
 
         let mut event_loop: EventLoop<App> = EventLoop::try_new()?;
         let event_loop_handler = event_loop.handle();
 
+        let (decoded, channel) = channel::channel();
+        event_loop_handler
+            .insert_source(channel, |event, _, app| {
+                if let channel::Event::Msg(decoded) = event {
+                    app.handle_decoded(decoded);
+                }
+            })
+            .map_err(|e| anyhow!("{e}"))
+            .context("Failed to insert decoder channel into event loop")?;
+        // Frames drawn with the software renderer are composed on the worker pool, which wakes up
+        // the event loop to draw them once they are ready
+        let (wake, ping_source) = ping::make_ping()?;
+        event_loop_handler
+            .insert_source(ping_source, |_, _, _| {})
+            .map_err(|e| anyhow!("{e}"))
+            .context("Failed to insert wake up source into event loop")?;
+
         let mut app = Self {
             conn,
             registry_state,
             output_state,
             compositor_state,
             layer_shell,
-            ctx,
-            animation,
-            layer,
-
-            img_dir,
-            interval,
-            configured: false,
-            frame_timer: FrameTimer::new(FPS),
-        };
+            shm,
+            protocols,
+            presentation_clock: None,
+            wallpapers: Vec::new(),
+            pending: Vec::new(),
+            closes: HashMap::new(),
+            retries: HashMap::new(),
+            qh,
+            loop_handle: event_loop_handler.clone(),
 
-        let _ = event_loop_handler.insert_source(
-            Timer::from_deadline(app.frame_timer.next_frame()),
-            |_, _, app| {
-                if app.frame_timer.start() {
-                    app.draw();
-                    if app.animation.is_finished() {
-                        app.frame_timer.set_fps(MIN_FPS);
-                    } else {
-                        app.frame_timer.set_fps(FPS);
-                    }
-                }
-                TimeoutAction::ToInstant(app.frame_timer.next_frame())
-            },
-        );
+            config,
+            config_path,
+            overrides,
+            loader: Arc::new(Mutex::new(loader)),
+            library: Rc::new(library),
+            decoded,
+            wake,
+            paused: false,
+            retry_timer: None,
+        };
 
-        event_loop_handler
-            .insert_source(Timer::from_duration(app.interval), |_, _, app| {
-                match app.load_img() {
-                    Ok(img) => {
-                        app.animation.update_img(&img, &app.ctx);
-                    }
-                    Err(e) => {
-                        error!("Could not load new img: {e}");
-                    }
-                }
-                TimeoutAction::ToDuration(app.interval)
-            })
-            .map_err(|e| anyhow!("{e}"))?;
+        let _socket = ipc::listen(&event_loop_handler)?;
+        if let Err(e) = config::watch(&app.config_path, &event_loop_handler) {
+            warn!("Config file will not be reloaded on changes: {e:#}");
+        }
 
         let loop_signal = event_loop.get_signal();
         ctrlc::set_handler(move || {
@@ -179,69 +217,585 @@ impl App {
             .insert(event_loop.handle())
             .map_err(|e| anyhow!("{e}"))
             .context("Failed to insert wayland source into event loop")?;
-        event_loop.run(None, &mut app, |_| ())?;
+        // Anything handled in an iteration may have started an animation or be a frame callback
+        // asking for the next frame
+        event_loop.run(None, &mut app, App::draw)?;
         Ok(())
     }
 
+    /// Draws the next frame of every wallpaper that is animating and whose previous frame was
+    /// shown. Frames are paced by the frame callbacks of the compositor, so that animations run
+    /// at the refresh rate of each output.
     fn draw(&mut self) {
-        self.animation.render(&self.ctx);
+        let qh = &self.qh;
+        let mut failed = false;
+        for wallpaper in &mut self.wallpapers {
+            failed |= !wallpaper.draw(qh);
+        }
+        if !failed || self.retry_timer.is_some() {
+            return;
+        }
+        // Nothing else may wake up the event loop, so a timer does and the frames are drawn again
+        let timer =
+            self.loop_handle
+                .insert_source(Timer::from_duration(RETRY_DELAY), |_, _, app| {
+                    app.retry_timer = None;
+                    TimeoutAction::Drop
+                });
+        match timer {
+            Ok(token) => self.retry_timer = Some(token),
+            Err(e) => error!("Failed to schedule drawing again: {e}"),
+        }
+    }
+
+    /// Starts decoding the first image for `output`, the wallpaper is created once it is ready.
+    fn add_output(&mut self, output: wl_output::WlOutput) {
+        if self.pending.contains(&output) || self.wallpapers.iter().any(|w| *w.output() == output) {
+            return;
+        }
+        info!("Loading image for output {}", self.output_name(&output));
+        self.spawn_load(&output, Purpose::Create);
+        self.pending.push(output);
+    }
+
+    fn create_wallpaper(
+        &mut self,
+        output: wl_output::WlOutput,
+        img: (PathBuf, DynamicImage),
+    ) -> Result<()> {
+        info!(
+            "Creating wallpaper for output {}",
+            self.output_name(&output)
+        );
+        let settings = self.output_settings(&output);
+        let timer = self.insert_timer(&output, settings.interval)?;
+
+        let renderer = match self.wallpapers.first() {
+            _ if self.config.software => RendererChoice::Software(&self.shm, &self.wake),
+            // Only the first wallpaper tries to get a GPU, the others use the same renderer
+            Some(first) => match first.gpu() {
+                Some(gpu) => RendererChoice::Gpu(Some(gpu.clone()), &self.shm, &self.wake),
+                None => RendererChoice::Software(&self.shm, &self.wake),
+            },
+            None => RendererChoice::Gpu(None, &self.shm, &self.wake),
+        };
+        let wallpaper = Wallpaper::new(
+            &self.conn,
+            &self.qh,
+            &self.compositor_state,
+            &self.layer_shell,
+            &self.config,
+            settings,
+            output.clone(),
+            renderer,
+            &self.protocols,
+            &self.library,
+            img,
+            timer,
+        );
+        let mut wallpaper = match wallpaper {
+            Ok(wallpaper) => wallpaper,
+            Err(e) => {
+                self.loop_handle.remove(timer);
+                return Err(e);
+            }
+        };
+        if let Some(info) = self.output_state.info(&output) {
+            wallpaper.update_mode(&info);
+        }
+        self.wallpapers.push(wallpaper);
+        self.prefetch(&output)
     }
 
-    fn load_random_img(dir: &Path) -> Result<DynamicImage> {
-        let mut rng = rand::thread_rng();
-        let mut files: Vec<_> = dir
-            .read_dir()?
-            .filter_map(Result::ok)
-            .map(|d| d.path())
-            .filter(|p| p.is_file())
+    fn remove_output(&mut self, output: &wl_output::WlOutput) {
+        self.pending.retain(|o| o != output);
+        let Some(idx) = self.wallpapers.iter().position(|w| w.output() == output) else {
+            return;
+        };
+        info!("Removing wallpaper for output {}", self.output_name(output));
+        let wallpaper = self.wallpapers.swap_remove(idx);
+        self.loop_handle.remove(wallpaper.timer());
+    }
+
+    /// Inserts the timer switching the image of `output` every `interval`.
+    fn insert_timer(
+        &self,
+        output: &wl_output::WlOutput,
+        interval: Duration,
+    ) -> Result<RegistrationToken> {
+        let output = output.clone();
+        self.loop_handle
+            .insert_source(Timer::from_duration(interval), move |_, _, app| {
+                if !app.paused {
+                    if let Err(e) = app.next_img(&output) {
+                        error!("Could not switch to next img: {e:#}");
+                    }
+                }
+                match app.wallpapers.iter().find(|w| *w.output() == output) {
+                    Some(wallpaper) => TimeoutAction::ToDuration(wallpaper.settings().interval),
+                    None => TimeoutAction::Drop,
+                }
+            })
+            .map_err(|e| anyhow!("{e}"))
+    }
+
+    /// Reads the config file again and applies the changes to all outputs. The previous config is
+    /// kept if the new one is invalid.
+    pub fn reload_config(&mut self) {
+        let loaded = Config::load(&self.config_path, &self.overrides).and_then(|config| {
+            let loader = ImageLoader::new(&config)?;
+            let library = ShaderLibrary::load(&config::transitions_dir(&self.config_path));
+            library.check(config.transition_kinds())?;
+            Ok((config, loader, library))
+        });
+        let (config, loader, library) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!("Failed to reload config, keeping the previous one: {e:#}");
+                return;
+            }
+        };
+        let library_changed = library != *self.library;
+        if config == self.config && !library_changed {
+            return;
+        }
+        info!("Config changed, applying");
+        self.library = Rc::new(library);
+        let recreate = config.layer != self.config.layer
+            || config.namespace != self.config.namespace
+            || config.software != self.config.software;
+        self.config = config;
+        let sources_changed = lock(&self.loader).update(loader);
+
+        let outputs: Vec<_> = self.wallpapers.iter().map(|w| w.output().clone()).collect();
+        for output in outputs {
+            if sources_changed {
+                if let Err(e) = self.discard_prefetch(&output) {
+                    error!("Could not load image from new sources: {e:#}");
+                }
+            }
+            if recreate {
+                // Layer and namespace can only be set when creating a layer surface, and the
+                // renderer is chosen with the first wallpaper
+                self.remove_output(&output);
+                self.add_output(output);
+            } else if let Err(e) = self.apply_settings(&output, library_changed) {
+                error!(
+                    "Could not apply config to output {}: {e:#}",
+                    self.output_name(&output)
+                );
+            }
+        }
+    }
+
+    /// Applies the config to the wallpaper of `output`, rebuilding its animation if `rebuild` is
+    /// set or the transition changed.
+    fn apply_settings(&mut self, output: &wl_output::WlOutput, rebuild: bool) -> Result<()> {
+        let settings = self.output_settings(output);
+        let wallpaper = self.wallpaper_mut(output)?;
+        let old = wallpaper.settings().clone();
+        if old == settings && !rebuild {
+            return Ok(());
+        }
+        let rebuild = rebuild || wallpaper::needs_rebuild(&old, &settings);
+        let current = wallpaper
+            .history()
+            .current()
+            .filter(|_| rebuild)
+            .map(Path::to_path_buf);
+        let library = self.library.clone();
+        let interval = settings.interval;
+        let wallpaper = self.wallpaper_mut(output)?;
+        // The animation is rebuilt once the current image is decoded again
+        wallpaper.set_settings(settings, &library, None);
+        if old.interval != interval {
+            let timer = wallpaper.timer();
+            self.loop_handle.remove(timer);
+            let timer = self.insert_timer(output, interval)?;
+            self.wallpaper_mut(output)?.set_timer(timer);
+        }
+        if let Some(path) = current {
+            self.spawn_load(output, Purpose::Rebuild(path));
+        }
+        Ok(())
+    }
+
+    fn output_settings(&self, output: &wl_output::WlOutput) -> OutputSettings {
+        let name = self.output_state.info(output).and_then(|info| info.name);
+        self.config.output(name.as_deref())
+    }
+
+    /// Where images decoded for `output` are shown, `None` if its size is not known yet.
+    fn target(&self, output: &wl_output::WlOutput) -> Option<Target> {
+        let info = self.output_state.info(output)?;
+        let size = match info.modes.iter().find(|m| m.current) {
+            Some(mode) if transform::swaps_axes(info.transform) => {
+                (mode.dimensions.1, mode.dimensions.0)
+            }
+            Some(mode) => mode.dimensions,
+            None => {
+                let (width, height) = info.logical_size?;
+                (width * info.scale_factor, height * info.scale_factor)
+            }
+        };
+        let settings = self.output_settings(output);
+        Some(Target {
+            scaling: settings.scaling,
+            size: (size.0.try_into().ok()?, size.1.try_into().ok()?),
+            zoom: if settings.ken_burns.enabled {
+                settings.ken_burns.zoom[1]
+            } else {
+                1.0
+            },
+            max_dimension: self
+                .wallpapers
+                .first()
+                .and_then(Wallpaper::max_image_dimension),
+        })
+    }
+
+    pub fn handle_request(&mut self, request: Request) -> Response {
+        let outputs: Vec<_> = self.wallpapers.iter().map(|w| w.output().clone()).collect();
+        let result = match request {
+            Request::Next => self.for_each_output(&outputs, Self::next_img),
+            Request::Previous => self.for_each_output(&outputs, Self::previous_img),
+            Request::Pause => {
+                self.paused = true;
+                Ok(())
+            }
+            Request::Resume => {
+                self.paused = false;
+                Ok(())
+            }
+            Request::Set(path) => self.set_img(path),
+            Request::Current => {
+                return Response::Current {
+                    outputs: self.current_imgs(),
+                }
+            }
+            Request::Status => {
+                return Response::Status {
+                    paused: self.paused,
+                    outputs: self.output_statuses(),
+                }
+            }
+        };
+        match result {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::error(format!("{e:#}")),
+        }
+    }
+
+    /// Calls `f` for each of `outputs`, even after it failed for one. The errors of all outputs
+    /// are returned together.
+    fn for_each_output(
+        &mut self,
+        outputs: &[wl_output::WlOutput],
+        f: impl Fn(&mut Self, &wl_output::WlOutput) -> Result<()>,
+    ) -> Result<()> {
+        let errors: Vec<_> = outputs
+            .iter()
+            .filter_map(|output| {
+                let e = f(self, output).err()?;
+                Some(format!("{}: {e:#}", self.output_name(output)))
+            })
             .collect();
-        files.shuffle(&mut rng);
-        files
-            .into_iter()
-            .filter_map(|p| {
-                info!("Attempting to load {}", p.display());
-                image::open(&p).ok()
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("; ")))
+        }
+    }
+
+    fn current_imgs(&self) -> BTreeMap<String, Option<PathBuf>> {
+        self.wallpapers
+            .iter()
+            .map(|w| {
+                let path = w.history().current().map(Path::to_path_buf);
+                (self.output_name(w.output()), path)
             })
-            .next()
-            .map(|i| {
-                info!("success");
-                i
+            .collect()
+    }
+
+    fn output_statuses(&self) -> BTreeMap<String, OutputStatus> {
+        self.wallpapers
+            .iter()
+            .map(|w| {
+                let status = OutputStatus {
+                    image: w.history().current().map(Path::to_path_buf),
+                    interval: w.settings().interval.as_secs(),
+                };
+                (self.output_name(w.output()), status)
             })
-            .with_context(|| format!("Unable to open any file from {} as an image", dir.display()))
+            .collect()
     }
 
-    fn load_img(&self) -> Result<DynamicImage> {
-        Self::load_random_img(&self.img_dir)
+    fn wallpaper_mut(&mut self, output: &wl_output::WlOutput) -> Result<&mut Wallpaper> {
+        self.wallpapers
+            .iter_mut()
+            .find(|w| w.output() == output)
+            .context("No wallpaper for output")
+    }
+
+    /// Shows the next image in the history of `output`, or a new one if it is already showing the
+    /// latest image. A new image that is still being decoded is shown once it is ready.
+    fn next_img(&mut self, output: &wl_output::WlOutput) -> Result<()> {
+        let next = self.wallpaper_mut(output)?.history().peek(1);
+        if let Some(path) = next.map(Path::to_path_buf) {
+            self.spawn_load(output, Purpose::History { path, offset: 1 });
+            return Ok(());
+        }
+        let wallpaper = self.wallpaper_mut(output)?;
+        match wallpaper.set_prefetch(Prefetch::Loading { switch: true }) {
+            Prefetch::Ready(path, img) => {
+                wallpaper.push_img(path, &img);
+                self.prefetch(output)?;
+            }
+            Prefetch::Loading { .. } => {}
+            Prefetch::None => self.spawn_load(output, Purpose::Prefetch),
+        }
+        Ok(())
+    }
+
+    fn previous_img(&mut self, output: &wl_output::WlOutput) -> Result<()> {
+        let path = self
+            .wallpaper_mut(output)?
+            .history()
+            .peek(-1)
+            .context("No previous image to go back to")?
+            .to_path_buf();
+        self.spawn_load(output, Purpose::History { path, offset: -1 });
+        Ok(())
+    }
+
+    /// Shows the image at `path` on all outputs. It is decoded once on the worker pool and
+    /// downscaled for each output there, only reading its header here to report errors early.
+    fn set_img(&mut self, path: PathBuf) -> Result<()> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("{} does not exist", path.display()))?;
+        image::image_dimensions(&path)
+            .with_context(|| format!("Unable to open {} as an image", path.display()))?;
+        let targets: Vec<_> = self
+            .wallpapers
+            .iter()
+            .map(|w| (w.output().clone(), self.target(w.output())))
+            .collect();
+        let sender = self.decoded.clone();
+        rayon::spawn(move || {
+            let img = match load_path(&path) {
+                Ok(img) => img,
+                Err(e) => {
+                    error!("Could not set image: {e:#}");
+                    return;
+                }
+            };
+            for (output, target) in targets {
+                let img = target
+                    .and_then(|target| target.resized(&img))
+                    .unwrap_or_else(|| img.clone());
+                let _ = sender.send(Decoded {
+                    output,
+                    purpose: Purpose::Set,
+                    result: Ok((path.clone(), img)),
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn output_name(&self, output: &wl_output::WlOutput) -> String {
+        self.output_state
+            .info(output)
+            .and_then(|info| info.name)
+            .unwrap_or_else(|| "<unknown>".to_string())
+    }
+
+    /// Starts decoding the image shown after the current one on `output`.
+    fn prefetch(&mut self, output: &wl_output::WlOutput) -> Result<()> {
+        self.wallpaper_mut(output)?
+            .set_prefetch(Prefetch::Loading { switch: false });
+        self.spawn_load(output, Purpose::Prefetch);
+        Ok(())
+    }
+
+    /// Replaces an image decoded ahead of time, e.g. because the sources changed.
+    fn discard_prefetch(&mut self, output: &wl_output::WlOutput) -> Result<()> {
+        let wallpaper = self.wallpaper_mut(output)?;
+        if let Prefetch::Ready(..) = wallpaper.prefetch() {
+            self.prefetch(output)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the image for `purpose` on the worker pool, the next one from the sources unless
+    /// it names a path, delivering it to [`App::handle_decoded`].
+    fn spawn_load(&self, output: &wl_output::WlOutput, purpose: Purpose) {
+        let loader = self.loader.clone();
+        let sender = self.decoded.clone();
+        let target = self.target(output);
+        let output = output.clone();
+        rayon::spawn(move || {
+            let result = match &purpose {
+                Purpose::History { path, .. } | Purpose::Rebuild(path) => {
+                    load_path(path).map(|img| (path.clone(), img))
+                }
+                _ => image_loader::load_next(&loader),
+            };
+            let result = result.map(|(path, img)| match target {
+                Some(target) => (path, target.fit(img)),
+                None => (path, img),
+            });
+            // Sending only fails when the event loop is gone, i.e. while exiting
+            let _ = sender.send(Decoded {
+                output,
+                purpose,
+                result,
+            });
+        });
+    }
+
+    fn handle_decoded(&mut self, decoded: Decoded) {
+        let Decoded {
+            output,
+            purpose,
+            result,
+        } = decoded;
+        let name = self.output_name(&output);
+        let result = match purpose {
+            Purpose::Create => {
+                let Some(idx) = self.pending.iter().position(|o| *o == output) else {
+                    // The output went away while decoding
+                    return;
+                };
+                self.pending.swap_remove(idx);
+                result.and_then(|img| self.create_wallpaper(output, img))
+            }
+            Purpose::Prefetch => {
+                let Ok(wallpaper) = self.wallpaper_mut(&output) else {
+                    return;
+                };
+                let switch = matches!(wallpaper.prefetch(), Prefetch::Loading { switch: true });
+                match result {
+                    Ok((path, img)) if switch => {
+                        wallpaper.push_img(path, &img);
+                        self.prefetch(&output)
+                    }
+                    Ok((path, img)) => {
+                        wallpaper.set_prefetch(Prefetch::Ready(path, img));
+                        Ok(())
+                    }
+                    Err(e) => {
+                        wallpaper.set_prefetch(Prefetch::None);
+                        Err(e)
+                    }
+                }
+            }
+            Purpose::History { offset, .. } => {
+                let Ok(wallpaper) = self.wallpaper_mut(&output) else {
+                    return;
+                };
+                result.map(|(path, img)| {
+                    // Nothing to do if the history moved on while decoding
+                    if wallpaper.history().peek(offset) == Some(&path) {
+                        wallpaper.history_mut().seek(offset);
+                        wallpaper.update_img(&img);
+                    }
+                })
+            }
+            Purpose::Rebuild(_) => {
+                let library = self.library.clone();
+                let Ok(wallpaper) = self.wallpaper_mut(&output) else {
+                    return;
+                };
+                match result {
+                    Ok((path, img)) if wallpaper.history().current() == Some(&path) => {
+                        let settings = wallpaper.settings().clone();
+                        wallpaper.set_settings(settings, &library, Some(&img));
+                        Ok(())
+                    }
+                    Ok(_) => {
+                        // Another image is shown by now, rebuild with that one instead
+                        if let Some(current) = wallpaper.history().current() {
+                            let current = current.to_path_buf();
+                            self.spawn_load(&output, Purpose::Rebuild(current));
+                        }
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Purpose::Set => {
+                let Ok(wallpaper) = self.wallpaper_mut(&output) else {
+                    return;
+                };
+                result.map(|(path, img)| wallpaper.push_img(path, &img))
+            }
+        };
+        if let Err(e) = result {
+            error!("Could not load image for output {name}: {e:#}");
+        }
     }
 }
+
+fn lock(loader: &Mutex<ImageLoader>) -> MutexGuard<'_, ImageLoader> {
+    loader.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl ShmHandler for App {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+delegate_shm!(App);
+
 impl CompositorHandler for App {
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
-        // Not needed for this example.
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == surface)
+        {
+            wallpaper.set_scale_factor(new_factor, &self.qh);
+        }
     }
 
     fn transform_changed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
-        // Not needed for this example.
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == surface)
+        {
+            wallpaper.set_transform(new_transform, &self.qh);
+        }
     }
 
     fn frame(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == surface)
+        {
+            wallpaper.frame_done();
+        }
     }
 
     fn surface_enter(
@@ -266,6 +820,85 @@ impl CompositorHandler for App {
 }
 
 delegate_compositor!(App);
+
+impl Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, wl_surface::WlSurface> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &wp_fractional_scale_v1::WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        if let Some(wallpaper) = app
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == surface)
+        {
+            wallpaper.set_fractional_scale(scale, qh);
+        }
+    }
+}
+
+delegate_noop!(App: wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1);
+delegate_noop!(App: wp_viewporter::WpViewporter);
+delegate_noop!(App: wp_viewport::WpViewport);
+
+impl Dispatch<wp_presentation::WpPresentation, ()> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &wp_presentation::WpPresentation,
+        event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            app.presentation_clock = Some(clk_id);
+        }
+    }
+}
+
+impl Dispatch<wp_presentation_feedback::WpPresentationFeedback, wl_surface::WlSurface> for App {
+    fn event(
+        app: &mut Self,
+        _proxy: &wp_presentation_feedback::WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Discarded frames were replaced by a newer one before being shown and leave the clock
+        // as is
+        let wp_presentation_feedback::Event::Presented {
+            tv_sec_hi,
+            tv_sec_lo,
+            tv_nsec,
+            refresh,
+            ..
+        } = event
+        else {
+            return;
+        };
+        let Some(clock) = app.presentation_clock else {
+            return;
+        };
+        let secs = (u64::from(tv_sec_hi) << 32) | u64::from(tv_sec_lo);
+        let Some(time) = frame_clock::presentation_instant(clock, secs, tv_nsec) else {
+            return;
+        };
+        if let Some(wallpaper) = app
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == surface)
+        {
+            wallpaper.presented(time, Duration::from_nanos(refresh.into()));
+        }
+    }
+}
 impl OutputHandler for App {
     fn output_state(&mut self) -> &mut OutputState {
         &mut self.output_state
@@ -275,24 +908,55 @@ impl OutputHandler for App {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
+        self.add_output(output);
     }
 
     fn update_output(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
+        let Some(wallpaper) = self.wallpapers.iter_mut().find(|w| *w.output() == output) else {
+            // Creating the wallpaper may have failed when the output first appeared. Closed
+            // surfaces are left to the back-off in `closed` instead.
+            let gave_up = self
+                .closes
+                .get(&output)
+                .is_some_and(|closes| closes.len() >= MAX_CLOSES);
+            if !gave_up && !self.retries.contains_key(&output) {
+                self.new_output(conn, qh, output);
+            }
+            return;
+        };
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
+        if wallpaper.update_mode(&info) {
+            info!(
+                "Output {} changed mode, reconfiguring",
+                info.name.as_deref().unwrap_or("<unknown>")
+            );
+            // The image was downscaled for the previous mode
+            if let Some(path) = wallpaper.history().current().map(Path::to_path_buf) {
+                self.spawn_load(&output, Purpose::Rebuild(path));
+            }
+        }
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
+        self.closes.remove(&output);
+        if let Some(timer) = self.retries.remove(&output) {
+            self.loop_handle.remove(timer);
+        }
+        self.remove_output(&output);
     }
 }
 delegate_output!(App);
@@ -302,23 +966,72 @@ impl LayerShellHandler for App {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
-        _configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
+        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
+        configure: smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        self.ctx.resize(_configure.new_size);
-        self.configured = true;
-        self.draw();
-        // _layer.set_size(self.dimensions.0, self.dimensions.1);
+        if let Some(wallpaper) = self
+            .wallpapers
+            .iter_mut()
+            .find(|w| w.layer().wl_surface() == layer.wl_surface())
+        {
+            wallpaper.configure(configure.new_size, &self.qh);
+        }
     }
 
     fn closed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
+        layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface,
     ) {
-        warn!("Surface closed");
+        let Some(output) = self
+            .wallpapers
+            .iter()
+            .find(|w| w.layer().wl_surface() == layer.wl_surface())
+            .map(|w| w.output().clone())
+        else {
+            return;
+        };
+        let name = self.output_name(&output);
+        warn!("Surface of output {name} closed");
+        self.remove_output(&output);
+        // The compositor may close surfaces of outputs that are still around, e.g. when they are
+        // disabled and re-enabled, so the wallpaper is recreated. A compositor refusing the
+        // surface would close it again right away, so back off and eventually give up.
+        let now = Instant::now();
+        let closes = self.closes.entry(output.clone()).or_default();
+        closes.retain(|&t| now.duration_since(t) < CLOSE_WINDOW);
+        closes.push(now);
+        if closes.len() >= MAX_CLOSES {
+            error!(
+                "Surface of output {name} was closed {MAX_CLOSES} times within {}s, not \
+                 recreating it",
+                CLOSE_WINDOW.as_secs()
+            );
+            return;
+        }
+        let delay = Duration::from_secs(1 << (closes.len() - 1));
+        info!(
+            "Recreating surface of output {name} in {}s",
+            delay.as_secs()
+        );
+        let retry = output.clone();
+        let timer =
+            self.loop_handle
+                .insert_source(Timer::from_duration(delay), move |_, _, app| {
+                    app.retries.remove(&retry);
+                    if app.output_state.outputs().any(|o| o == retry) {
+                        app.add_output(retry.clone());
+                    }
+                    TimeoutAction::Drop
+                });
+        match timer {
+            Ok(timer) => {
+                self.retries.insert(output, timer);
+            }
+            Err(e) => error!("Could not recreate surface of output {name}: {e}"),
+        }
     }
 }
 delegate_layer!(App);
@@ -331,37 +1044,3 @@ impl ProvidesRegistryState for App {
     registry_handlers![OutputState];
 }
 delegate_registry!(App);
-
-struct FrameTimer {
-    fps: f32,
-    start: Instant,
-}
-
-impl FrameTimer {
-    fn new(fps: f32) -> Self {
-        Self {
-            fps,
-            start: Instant::now(),
-        }
-    }
-
-    fn frametime(&self) -> Duration {
-        Duration::from_secs_f32(1.0 / self.fps)
-    }
-
-    fn start(&mut self) -> bool {
-        if (self.start + self.frametime()) > Instant::now() {
-            return false;
-        }
-        self.start = Instant::now();
-        true
-    }
-
-    fn next_frame(&self) -> Instant {
-        self.start + self.frametime()
-    }
-
-    fn set_fps(&mut self, fps: f32) {
-        self.fps = fps;
-    }
-}
@@ -1,24 +1,184 @@
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf};
 
 use anyhow::{bail, Result};
-use clap::{arg, Parser};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    config::{Config, Overrides, TransitionKind},
+    image_loader::Order,
+    ipc::{Request, Response},
+    render::animation::Easing,
+};
 
 #[derive(Parser)]
+#[command(version, about)]
 pub struct Cli {
-    /// Interval in seconds between image switches
-    #[arg(short, long, default_value_t = 60)]
-    interval: u64,
+    /// Print replies from the daemon as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
 
-    /// Directory of images
-    dir: PathBuf,
+#[derive(Subcommand)]
+enum Command {
+    /// Run the wallpaper daemon
+    Daemon(DaemonArgs),
+    /// Switch to the next image
+    Next,
+    /// Go back to the previous image
+    #[command(alias = "previous")]
+    Prev,
+    /// Stop switching images
+    Pause,
+    /// Resume switching images
+    Resume,
+    /// Show an image on all outputs
+    Set {
+        /// Image to show
+        file: PathBuf,
+    },
+    /// Print the image shown on each output
+    Current,
+    /// Print the state of the daemon
+    Status,
+}
+
+#[derive(Args)]
+struct DaemonArgs {
+    /// Interval in seconds between image switches [default: 60]
+    #[arg(short, long)]
+    interval: Option<u64>,
+
+    /// Order in which images are shown [default: shuffle]
+    #[arg(short, long)]
+    order: Option<Order>,
+
+    /// Show images in reverse order
+    #[arg(short, long)]
+    reverse: bool,
+
+    /// Show images in order, even if the config file reverses it
+    #[arg(long, conflicts_with = "reverse")]
+    no_reverse: bool,
+
+    /// Transition between images: none, fade, wipe, slide, zoom, dissolve, circle, pixelate,
+    /// random or the name of a shader in the transitions directory [default: fade]
+    #[arg(short, long)]
+    transition: Option<TransitionKind>,
+
+    /// Length of transitions in seconds [default: 8]
+    #[arg(short, long)]
+    duration: Option<f32>,
+
+    /// Easing curve of transitions: linear, ease-in, ease-out, ease-in-out, ease-in-quad and
+    /// the other combinations of in, out or in-out with quad, cubic, quart or quint, or
+    /// "cubic-bezier(x1, y1, x2, y2)" [default: ease-in-out]
+    #[arg(short, long)]
+    easing: Option<Easing>,
+
+    /// Slowly pan and zoom images while they are shown
+    #[arg(short, long)]
+    ken_burns: bool,
+
+    /// Render on the CPU instead of the GPU
+    #[arg(long)]
+    software: bool,
+
+    /// Config file [default: $XDG_CONFIG_HOME/wallswitcher/config.toml]
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Directories and files of images, replacing the sources of the config file
+    sources: Vec<PathBuf>,
+}
+
+/// What the invocation asks for, either running the daemon or sending it a request.
+pub enum Action {
+    Daemon {
+        config: PathBuf,
+        overrides: Overrides,
+    },
+    Request {
+        request: Request,
+        json: bool,
+    },
 }
 
 impl Cli {
-    pub fn parse_and_validate() -> Result<(PathBuf, Duration)> {
+    pub fn parse_and_validate() -> Result<Action> {
         let args = Cli::parse();
-        if !args.dir.is_dir() {
-            bail!("{} is not an existing directory", args.dir.display());
+        let request = match args.command {
+            Command::Daemon(daemon) => {
+                let config = match daemon.config {
+                    Some(path) => path,
+                    None => Config::default_path()?,
+                };
+                return Ok(Action::Daemon {
+                    config,
+                    overrides: Overrides {
+                        interval: daemon.interval,
+                        sources: daemon.sources,
+                        order: daemon.order,
+                        reverse: match (daemon.reverse, daemon.no_reverse) {
+                            (true, _) => Some(true),
+                            (_, true) => Some(false),
+                            _ => None,
+                        },
+                        transition: daemon.transition,
+                        duration: daemon.duration,
+                        easing: daemon.easing,
+                        ken_burns: daemon.ken_burns,
+                        software: daemon.software,
+                    },
+                });
+            }
+            Command::Next => Request::Next,
+            Command::Prev => Request::Previous,
+            Command::Pause => Request::Pause,
+            Command::Resume => Request::Resume,
+            Command::Set { file } => Request::Set(std::path::absolute(&file)?),
+            Command::Current => Request::Current,
+            Command::Status => Request::Status,
+        };
+        Ok(Action::Request {
+            request,
+            json: args.json,
+        })
+    }
+}
+
+/// Prints a reply from the daemon, turning error replies into errors.
+pub fn print_response(response: &Response, json: bool) -> Result<()> {
+    if let Response::Error { message } = response {
+        bail!("{message}");
+    }
+    if json {
+        println!("{}", serde_json::to_string(response)?);
+        return Ok(());
+    }
+    match response {
+        Response::Ok | Response::Error { .. } => {}
+        Response::Current { outputs } => print_outputs(outputs),
+        Response::Status { paused, outputs } => {
+            println!("paused: {paused}");
+            for (output, status) in outputs {
+                let path = status.image.as_ref().map(|p| p.display().to_string());
+                println!(
+                    "{output}: {} (every {}s)",
+                    path.as_deref().unwrap_or("-"),
+                    status.interval
+                );
+            }
         }
-        Ok((args.dir, Duration::from_secs(args.interval)))
+    }
+    Ok(())
+}
+
+fn print_outputs(outputs: &BTreeMap<String, Option<PathBuf>>) {
+    for (output, path) in outputs {
+        let path = path.as_ref().map(|p| p.display().to_string());
+        println!("{output}: {}", path.as_deref().unwrap_or("-"));
     }
 }
@@ -0,0 +1,710 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    iter::once,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use inotify::{Inotify, WatchMask};
+use log::*;
+use serde::Deserialize;
+use smithay_client_toolkit::{
+    reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+    shell::wlr_layer::Layer,
+};
+
+use crate::{
+    app::App,
+    image_loader::{Filter, Order},
+    render::{
+        animation::Easing,
+        placement::{Background, ScaleMode},
+        Scaling,
+    },
+};
+
+/// Settings read from the config file, see [`Config::default_path`].
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Directories and files of images
+    pub sources: Vec<Source>,
+    /// Named groups of sources, each picked from as a whole according to its weight. Sorted by
+    /// name, so that reloading an unchanged config keeps the sources in the same order.
+    pub collections: BTreeMap<String, Collection>,
+    pub scan: Scan,
+    pub order: Order,
+    /// Whether to show images in the opposite of `order`
+    pub reverse: bool,
+    /// Interval in seconds between image switches
+    pub interval: u64,
+    /// Render on the CPU into shared memory instead of on the GPU, which is done anyway if there
+    /// is no usable GPU
+    pub software: bool,
+    pub layer: LayerKind,
+    /// Namespace of the layer surfaces
+    pub namespace: String,
+    pub transition: Transition,
+    pub scaling: ScaleMode,
+    /// What fills the bars left by the fit and center scale modes, a color or "blur"
+    pub background: Background,
+    pub ken_burns: KenBurns,
+    /// Overrides keyed by output name, e.g. "DP-1"
+    pub outputs: HashMap<String, OutputConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            collections: BTreeMap::new(),
+            scan: Scan::default(),
+            order: Order::default(),
+            reverse: false,
+            interval: 60,
+            software: false,
+            layer: LayerKind::Background,
+            namespace: "wallpaper".to_string(),
+            transition: Transition::default(),
+            scaling: ScaleMode::default(),
+            background: Background::default(),
+            ken_burns: KenBurns::default(),
+            outputs: HashMap::new(),
+        }
+    }
+}
+
+/// A directory or file of images, written as a path or a table with a path and weight.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "SourceEntry")]
+pub struct Source {
+    pub path: PathBuf,
+    /// How often images of this source are picked relative to other sources
+    pub weight: f32,
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Self { path, weight: 1.0 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceEntry {
+    Path(PathBuf),
+    #[serde(rename_all = "kebab-case")]
+    Weighted {
+        path: PathBuf,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+}
+
+impl From<SourceEntry> for Source {
+    fn from(entry: SourceEntry) -> Self {
+        match entry {
+            SourceEntry::Path(path) => path.into(),
+            SourceEntry::Weighted { path, weight } => Self { path, weight },
+        }
+    }
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// Sources pooled into a single source.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Collection {
+    pub sources: Vec<PathBuf>,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+/// How source directories are searched for images.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Scan {
+    /// How deep to descend into subdirectories, 1 only scanning the source directories
+    /// themselves. Unlimited if unset
+    pub max_depth: Option<usize>,
+    pub follow_symlinks: bool,
+    /// Whether to include hidden files and directories
+    pub hidden: bool,
+    /// Glob patterns like `**/*.{jpg,png}` selecting files, `!**/drafts/**` excluding them
+    pub filters: Vec<String>,
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            follow_symlinks: true,
+            hidden: false,
+            filters: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayerKind {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+impl From<LayerKind> for Layer {
+    fn from(layer: LayerKind) -> Self {
+        match layer {
+            LayerKind::Background => Layer::Background,
+            LayerKind::Bottom => Layer::Bottom,
+            LayerKind::Top => Layer::Top,
+            LayerKind::Overlay => Layer::Overlay,
+        }
+    }
+}
+
+/// Longest transition in seconds, longer ones are most likely mistakes and could not be
+/// represented as a [`Duration`].
+const MAX_TRANSITION_DURATION: f32 = 3600.0;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transition {
+    #[serde(rename = "type")]
+    pub kind: TransitionKind,
+    /// Length of the transition in seconds
+    pub duration: f32,
+    pub easing: Easing,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Self {
+            kind: TransitionKind::Fade,
+            duration: 8.0,
+            easing: Easing::default(),
+        }
+    }
+}
+
+impl Transition {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f32(self.duration)
+    }
+}
+
+/// Slow panning and zooming of the shown image, known as the Ken Burns effect.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KenBurns {
+    pub enabled: bool,
+    /// Smallest and largest zoom, relative to the image as placed by the scale mode
+    pub zoom: [f32; 2],
+    /// Number of times the image moves from one end of the zoom range to the other per interval
+    pub speed: f32,
+    /// Whether to zoom in or out and towards a random point for every image, instead of always
+    /// zooming into the center
+    pub random: bool,
+}
+
+impl Default for KenBurns {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zoom: [1.0, 1.2],
+            speed: 1.0,
+            random: true,
+        }
+    }
+}
+
+/// Kind of transition, written as the name of a built in transition or of a shader in the
+/// transitions directory, see [`ShaderLibrary`](crate::render::animation::ShaderLibrary).
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "String")]
+pub enum TransitionKind {
+    /// Switch images instantly
+    None,
+    /// Crossfade between the images
+    Fade,
+    /// Reveal the new image behind a moving edge
+    Wipe,
+    /// Push the old image out with the new one
+    Slide,
+    /// Zoom into the old image while fading to the new one
+    Zoom,
+    /// Replace the image in a random pattern of small cells
+    Dissolve,
+    /// Reveal the new image in a growing circle
+    Circle,
+    /// Pixelate the old image and resolve the new one from the pixels
+    Pixelate,
+    /// Pick a different transition for every switch
+    Random,
+    /// A shader from the transitions directory
+    Custom(String),
+}
+
+impl TransitionKind {
+    /// Built in kinds drawn with a transition shader.
+    pub const BUILT_IN: [Self; 7] = [
+        Self::Fade,
+        Self::Wipe,
+        Self::Slide,
+        Self::Zoom,
+        Self::Dissolve,
+        Self::Circle,
+        Self::Pixelate,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::None => "none",
+            Self::Fade => "fade",
+            Self::Wipe => "wipe",
+            Self::Slide => "slide",
+            Self::Zoom => "zoom",
+            Self::Dissolve => "dissolve",
+            Self::Circle => "circle",
+            Self::Pixelate => "pixelate",
+            Self::Random => "random",
+            Self::Custom(name) => name,
+        }
+    }
+}
+
+impl From<String> for TransitionKind {
+    fn from(name: String) -> Self {
+        [Self::None, Self::Random]
+            .into_iter()
+            .chain(Self::BUILT_IN)
+            .find(|k| k.name() == name)
+            .unwrap_or(Self::Custom(name))
+    }
+}
+
+impl FromStr for TransitionKind {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.to_string().into())
+    }
+}
+
+/// Settings overriding the global ones for a single output. Unset fields fall back to the global
+/// value.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub interval: Option<u64>,
+    pub transition: TransitionOverride,
+    pub scaling: Option<ScaleMode>,
+    pub background: Option<Background>,
+    #[serde(rename = "ken-burns")]
+    pub ken_burns: Option<KenBurns>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransitionOverride {
+    #[serde(rename = "type")]
+    pub kind: Option<TransitionKind>,
+    pub duration: Option<f32>,
+    pub easing: Option<Easing>,
+}
+
+/// Settings of a single output after applying its overrides.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSettings {
+    pub interval: Duration,
+    pub transition: Transition,
+    pub scaling: Scaling,
+    pub ken_burns: KenBurns,
+}
+
+/// Values given on the command line, which take precedence over the config file.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub interval: Option<u64>,
+    pub sources: Vec<PathBuf>,
+    pub order: Option<Order>,
+    pub reverse: Option<bool>,
+    pub transition: Option<TransitionKind>,
+    pub duration: Option<f32>,
+    pub easing: Option<Easing>,
+    pub ken_burns: bool,
+    pub software: bool,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/wallswitcher/config.toml`, falling back to `~/.config` if
+    /// `XDG_CONFIG_HOME` is not set.
+    pub fn default_path() -> Result<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => home_dir()?.join(".config"),
+        };
+        Ok(config_dir.join("wallswitcher").join("config.toml"))
+    }
+
+    /// Reads the config file at `path` and applies `overrides` on top of it. A missing file is
+    /// treated as empty.
+    pub fn load(path: &Path, overrides: &Overrides) -> Result<Self> {
+        let mut config = match std::fs::read_to_string(path) {
+            Ok(s) => {
+                toml::from_str(&s).with_context(|| format!("Failed to parse {}", path.display()))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        if let Some(interval) = overrides.interval {
+            config.interval = interval;
+        }
+        if let Some(order) = overrides.order {
+            config.order = order;
+        }
+        if let Some(reverse) = overrides.reverse {
+            config.reverse = reverse;
+        }
+        if let Some(kind) = &overrides.transition {
+            config.transition.kind = kind.clone();
+        }
+        if let Some(duration) = overrides.duration {
+            config.transition.duration = duration;
+        }
+        if let Some(easing) = overrides.easing {
+            config.transition.easing = easing;
+        }
+        config.ken_burns.enabled |= overrides.ken_burns;
+        config.software |= overrides.software;
+        if !overrides.sources.is_empty() {
+            config.sources = overrides
+                .sources
+                .iter()
+                .cloned()
+                .map(Source::from)
+                .collect();
+            config.collections.clear();
+        }
+        for source in &mut config.sources {
+            source.path = expand_home(&source.path)?;
+        }
+        for collection in config.collections.values_mut() {
+            for path in &mut collection.sources {
+                *path = expand_home(path)?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.sources.is_empty() && self.collections.is_empty() {
+            bail!("No image sources given on the command line or in the config file");
+        }
+        let paths = self
+            .sources
+            .iter()
+            .map(|s| &s.path)
+            .chain(self.collections.values().flat_map(|c| &c.sources));
+        if let Some(path) = paths.into_iter().find(|p| !p.exists()) {
+            bail!("Source {} does not exist", path.display());
+        }
+        let weights = self
+            .sources
+            .iter()
+            .map(|s| s.weight)
+            .chain(self.collections.values().map(|c| c.weight));
+        if weights.into_iter().any(|w| w <= 0.0 || !w.is_finite()) {
+            bail!("weight must be positive");
+        }
+        if self.scan.max_depth == Some(0) {
+            bail!("max-depth must be at least 1");
+        }
+        Filter::new(&self.scan.filters)?;
+        if self.interval == 0 || self.outputs.values().any(|o| o.interval == Some(0)) {
+            bail!("interval must be at least 1 second");
+        }
+        let durations = once(Some(self.transition.duration))
+            .chain(self.outputs.values().map(|o| o.transition.duration));
+        if durations
+            .flatten()
+            .any(|d| !(0.0..=MAX_TRANSITION_DURATION).contains(&d))
+        {
+            bail!("transition duration must be between 0 and {MAX_TRANSITION_DURATION} seconds");
+        }
+        let ken_burns = once(Some(&self.ken_burns))
+            .chain(self.outputs.values().map(|o| o.ken_burns.as_ref()))
+            .flatten();
+        for ken_burns in ken_burns {
+            let [min, max] = ken_burns.zoom;
+            if !(1.0 <= min && min <= max && max.is_finite()) {
+                bail!("ken-burns zoom must be [min, max] with 1 <= min <= max");
+            }
+            if ken_burns.speed <= 0.0 || !ken_burns.speed.is_finite() {
+                bail!("ken-burns speed must be positive");
+            }
+        }
+        Ok(())
+    }
+
+    /// Transition kinds used by any output.
+    pub fn transition_kinds(&self) -> impl Iterator<Item = &TransitionKind> {
+        once(&self.transition.kind).chain(
+            self.outputs
+                .values()
+                .filter_map(|o| o.transition.kind.as_ref()),
+        )
+    }
+
+    /// Settings for the output called `name`.
+    pub fn output(&self, name: Option<&str>) -> OutputSettings {
+        let Some(output) = name.and_then(|n| self.outputs.get(n)) else {
+            return OutputSettings {
+                interval: Duration::from_secs(self.interval),
+                transition: self.transition.clone(),
+                scaling: Scaling {
+                    mode: self.scaling,
+                    background: self.background,
+                },
+                ken_burns: self.ken_burns,
+            };
+        };
+        let overrides = &output.transition;
+        OutputSettings {
+            interval: Duration::from_secs(output.interval.unwrap_or(self.interval)),
+            transition: Transition {
+                kind: overrides
+                    .kind
+                    .clone()
+                    .unwrap_or_else(|| self.transition.kind.clone()),
+                duration: overrides.duration.unwrap_or(self.transition.duration),
+                easing: overrides.easing.unwrap_or(self.transition.easing),
+            },
+            scaling: Scaling {
+                mode: output.scaling.unwrap_or(self.scaling),
+                background: output.background.unwrap_or(self.background),
+            },
+            ken_burns: output.ken_burns.unwrap_or(self.ken_burns),
+        }
+    }
+}
+
+fn home_dir() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .context("HOME is not set")
+}
+
+fn expand_home(path: &Path) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Ok(rest) => Ok(home_dir()?.join(rest)),
+        Err(_) => Ok(path.to_path_buf()),
+    }
+}
+
+/// Directory of custom transition shaders belonging to the config file at `config_path`.
+pub fn transitions_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("transitions")
+}
+
+/// Watches the config file at `path` and the transitions directory next to it, calling
+/// [`App::reload_config`] whenever either changes.
+///
+/// The containing directory is watched rather than the file itself, as editors often replace the
+/// file instead of writing to it.
+pub fn watch(path: &Path, handle: &LoopHandle<'static, App>) -> Result<()> {
+    let dir = path
+        .parent()
+        .context("Config file has no parent directory")?;
+    let file_name = path
+        .file_name()
+        .context("Config path has no file name")?
+        .to_owned();
+
+    let inotify = Inotify::init().context("Failed to initialize inotify")?;
+    let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE;
+    inotify
+        .watches()
+        .add(dir, mask)
+        .with_context(|| format!("Failed to watch {}", dir.display()))?;
+    // Only watched if it exists when starting
+    let transitions = inotify.watches().add(transitions_dir(path), mask).ok();
+
+    let mut buf = [0; 4096];
+    handle
+        .insert_source(
+            Generic::new(inotify, Interest::READ, Mode::Level),
+            move |_, inotify, app| {
+                // SAFETY: the inotify instance is not dropped or replaced
+                let inotify = unsafe { inotify.get_mut() };
+                let mut changed = false;
+                loop {
+                    match inotify.read_events(&mut buf) {
+                        Ok(events) => {
+                            let mut events = events.peekable();
+                            if events.peek().is_none() {
+                                break;
+                            }
+                            changed |= events.any(|e| {
+                                e.name == Some(file_name.as_os_str())
+                                    || transitions.as_ref() == Some(&e.wd)
+                            });
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                        // Returning the error would stop the event loop
+                        Err(e) => {
+                            error!("Failed to read config changes, no longer watching: {e}");
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+                }
+                if changed {
+                    app.reload_config();
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow!("{e}"))
+        .context("Failed to insert config watcher into event loop")?;
+    info!("Watching {} for changes", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn config(source: &TempDir) -> Config {
+        Config {
+            sources: vec![source.path().to_path_buf().into()],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                sources = [{:?}]
+                interval = 30
+                order = "name"
+                reverse = true
+
+                [collections.photos]
+                sources = [{:?}]
+
+                [transition]
+                type = "wipe"
+                duration = 2.0
+                easing = "linear"
+                "#,
+                dir.path(),
+                dir.path()
+            ),
+        )
+        .unwrap();
+
+        let config = Config::load(&path, &Overrides::default()).unwrap();
+        assert_eq!(config.interval, 30);
+        assert_eq!(config.order, Order::Name);
+        assert!(config.reverse);
+        assert_eq!(config.transition.kind, TransitionKind::Wipe);
+        assert_eq!(config.transition.duration, 2.0);
+        assert_eq!(config.transition.easing, Easing::Linear);
+        assert_eq!(config.collections.len(), 1);
+
+        let sources = tempfile::tempdir().unwrap();
+        let overrides = Overrides {
+            interval: Some(10),
+            sources: vec![sources.path().to_path_buf()],
+            order: Some(Order::Mtime),
+            reverse: Some(false),
+            transition: Some(TransitionKind::Slide),
+            duration: Some(1.5),
+            easing: Some(Easing::EaseIn),
+            ken_burns: true,
+            software: true,
+        };
+        let config = Config::load(&path, &overrides).unwrap();
+        assert_eq!(config.interval, 10);
+        assert_eq!(config.order, Order::Mtime);
+        assert!(!config.reverse);
+        assert_eq!(
+            config.transition,
+            Transition {
+                kind: TransitionKind::Slide,
+                duration: 1.5,
+                easing: Easing::EaseIn,
+            }
+        );
+        assert!(config.ken_burns.enabled);
+        assert!(config.software);
+        // Sources given on the command line replace all sources of the file
+        assert_eq!(config.sources, [Source::from(sources.path().to_path_buf())]);
+        assert!(config.collections.is_empty());
+    }
+
+    #[test]
+    fn missing_file_uses_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let overrides = Overrides {
+            sources: vec![dir.path().to_path_buf()],
+            ..Overrides::default()
+        };
+        let config = Config::load(&dir.path().join("config.toml"), &overrides).unwrap();
+        assert_eq!(config, self::config(&dir));
+
+        assert!(Config::load(&dir.path().join("config.toml"), &Overrides::default()).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let dir = tempfile::tempdir().unwrap();
+        config(&dir).validate().unwrap();
+
+        let invalid: &[fn(&mut Config)] = &[
+            |c| c.sources.clear(),
+            |c| c.sources[0].path.push("missing"),
+            |c| c.sources[0].weight = 0.0,
+            |c| c.scan.max_depth = Some(0),
+            |c| c.scan.filters = vec!["a[".to_string()],
+            |c| c.interval = 0,
+            |c| {
+                let output = OutputConfig {
+                    interval: Some(0),
+                    ..OutputConfig::default()
+                };
+                c.outputs.insert("DP-1".to_string(), output);
+            },
+            |c| c.transition.duration = -1.0,
+            |c| c.transition.duration = f32::NAN,
+            |c| c.transition.duration = MAX_TRANSITION_DURATION * 2.0,
+            |c| c.ken_burns.zoom = [1.2, 1.1],
+            |c| c.ken_burns.zoom = [0.5, 1.2],
+            |c| c.ken_burns.speed = 0.0,
+        ];
+        for (i, invalidate) in invalid.iter().enumerate() {
+            let mut config = config(&dir);
+            invalidate(&mut config);
+            assert!(config.validate().is_err(), "case {i}");
+        }
+    }
+}
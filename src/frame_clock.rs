@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+/// Predicts when the next frame of a surface is shown, so that animations are drawn for the
+/// moment they appear on screen rather than for when they happen to be drawn.
+#[derive(Default)]
+pub struct FrameClock {
+    /// When the last frame was presented
+    presented: Option<Instant>,
+    /// Time between two refreshes of the output, zero if unknown or variable
+    refresh: Duration,
+}
+
+impl FrameClock {
+    /// Records presentation feedback for a frame.
+    pub fn presented(&mut self, time: Instant, refresh: Duration) {
+        self.presented = Some(time);
+        self.refresh = refresh;
+    }
+
+    /// Time the frame drawn now will be shown at. Without feedback, e.g. if the compositor does
+    /// not support `wp_presentation`, this is the current time.
+    pub fn next_frame(&self) -> Instant {
+        let now = Instant::now();
+        match self.presented {
+            Some(presented) if !self.refresh.is_zero() => {
+                // The first refresh after now
+                let periods = now.saturating_duration_since(presented).as_nanos()
+                    / self.refresh.as_nanos()
+                    + 1;
+                presented + self.refresh * periods.try_into().unwrap_or(u32::MAX)
+            }
+            _ => now,
+        }
+    }
+}
+
+/// Converts a `wp_presentation` timestamp in the clock `clock_id` to an [`Instant`], `None` if
+/// the clock cannot be read.
+pub fn presentation_instant(clock_id: u32, secs: u64, nanos: u32) -> Option<Instant> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(clock_id as libc::clockid_t, &mut now) } != 0 {
+        return None;
+    }
+    let now = Duration::new(now.tv_sec.try_into().ok()?, now.tv_nsec.try_into().ok()?);
+    let age = now.saturating_sub(Duration::new(secs, nanos));
+    Instant::now().checked_sub(age)
+}
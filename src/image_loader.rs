@@ -1,62 +1,525 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
 
 use anyhow::{Context, Result};
-use image::DynamicImage;
-use rand::seq::SliceRandom;
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use log::*;
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use serde::Deserialize;
+use walkdir::{DirEntry, WalkDir};
 
-use crate::animation::{Fade, Static};
+use crate::{
+    config::{Config, Scan},
+    render::Scaling,
+};
 
+/// In which order images of a source are shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Order {
+    /// Show every image once in random order before reshuffling
+    #[default]
+    Shuffle,
+    /// Pick any image each time, possibly repeating
+    Random,
+    /// Sorted by path
+    Name,
+    /// Oldest modification time first
+    Mtime,
+}
+
+/// Finds images in the sources and picks the ones to show.
 pub struct ImageLoader {
-    image_dir: PathBuf,
-    current_img: Option<DynamicImage>,
+    groups: Vec<Group>,
+    scan: Scan,
+    filter: Filter,
+    order: Order,
+    reverse: bool,
+}
+
+/// Paths whose images are pooled together, picked from according to `weight`.
+struct Group {
+    paths: Vec<PathBuf>,
+    weight: f32,
+    /// Files left to show before the group is scanned again
+    queue: VecDeque<PathBuf>,
 }
 
 impl ImageLoader {
-    pub fn new(image_dir: PathBuf) -> Self {
-        Self {
-            image_dir,
-            current_img: None,
+    pub fn new(config: &Config) -> Result<Self> {
+        let sources = config.sources.iter().map(|s| Group {
+            paths: vec![s.path.clone()],
+            weight: s.weight,
+            queue: VecDeque::new(),
+        });
+        let collections = config.collections.values().map(|c| Group {
+            paths: c.sources.clone(),
+            weight: c.weight,
+            queue: VecDeque::new(),
+        });
+        Ok(Self {
+            groups: sources.chain(collections).collect(),
+            scan: config.scan.clone(),
+            filter: Filter::new(&config.scan.filters)?,
+            order: config.order,
+            reverse: config.reverse,
+        })
+    }
+
+    /// Takes over the settings of `other`, keeping the position in the current order if they
+    /// select the same images in the same order. Returns whether anything changed.
+    pub fn update(&mut self, other: Self) -> bool {
+        let same = self.scan == other.scan
+            && self.order == other.order
+            && self.reverse == other.reverse
+            && self.groups.len() == other.groups.len()
+            && self
+                .groups
+                .iter()
+                .zip(&other.groups)
+                .all(|(a, b)| a.paths == b.paths && a.weight == b.weight);
+        if !same {
+            *self = other;
         }
+        !same
     }
-    fn get_next_img(&self) -> Result<DynamicImage> {
-        let mut rng = rand::thread_rng();
-        let mut files: Vec<_> = self
-            .image_dir
-            .read_dir()?
-            .filter_map(Result::ok)
-            .map(|d| d.path())
-            .filter(|p| p.is_file())
+
+    /// Files of the group at `idx` in the configured order.
+    fn scan(&self, idx: usize) -> VecDeque<PathBuf> {
+        let mut files: Vec<_> = self.groups[idx]
+            .paths
+            .iter()
+            .flat_map(|p| self.scan_path(p))
             .collect();
-        files.shuffle(&mut rng);
-        files
+        match self.order {
+            Order::Shuffle | Order::Random => files.shuffle(&mut rand::thread_rng()),
+            Order::Name => files.sort(),
+            Order::Mtime => {
+                files.sort_by_cached_key(|p| p.metadata().and_then(|m| m.modified()).ok())
+            }
+        }
+        if self.reverse {
+            files.reverse();
+        }
+        files.into()
+    }
+
+    /// Files below `path`, or `path` itself if it is a file.
+    fn scan_path<'a>(&'a self, path: &'a Path) -> Box<dyn Iterator<Item = PathBuf> + 'a> {
+        if path.is_file() {
+            return Box::new(std::iter::once(path.to_path_buf()));
+        }
+        let mut walk = WalkDir::new(path)
+            .min_depth(1)
+            .follow_links(self.scan.follow_symlinks);
+        if let Some(depth) = self.scan.max_depth {
+            walk = walk.max_depth(depth);
+        }
+        let files = walk
             .into_iter()
-            .filter_map(|p| {
-                println!("Attempting to load {}", p.display());
-                image::open(p).ok()
-            })
-            .next()
-            .map(|i| {
-                println!("success");
-                i
+            .filter_entry(|e| self.scan.hidden || !is_hidden(e))
+            .filter_map(|e| {
+                e.inspect_err(|e| warn!("Skipping unreadable entry: {e}"))
+                    .ok()
             })
-            .with_context(|| {
-                format!(
-                    "Unable to open any file from {} as an image",
-                    self.image_dir.display()
-                )
+            .filter(|e| e.file_type().is_file())
+            .map(DirEntry::into_path)
+            .filter(move |p| {
+                let relative = p.strip_prefix(path).unwrap_or(p);
+                self.filter.matches(relative)
+            });
+        Box::new(files)
+    }
+
+    /// Picks the next file to show, the source according to the weights and the file according
+    /// to the order. `rescanned` holds the sources scanned again while looking for this image,
+    /// which are out of files that can be opened once their queue is empty again.
+    fn next_path(&mut self, rescanned: &mut HashSet<usize>) -> Option<PathBuf> {
+        let mut rng = rand::thread_rng();
+        loop {
+            let candidates: Vec<usize> = (0..self.groups.len())
+                .filter(|i| !(rescanned.contains(i) && self.groups[*i].queue.is_empty()))
+                .collect();
+            let weights = candidates.iter().map(|&i| self.groups[i].weight);
+            let dist = WeightedIndex::new(weights).ok()?;
+            let idx = candidates[dist.sample(&mut rng)];
+            if self.groups[idx].queue.is_empty() {
+                rescanned.insert(idx);
+                self.groups[idx].queue = self.scan(idx);
+                continue;
+            }
+            let queue = &mut self.groups[idx].queue;
+            return match self.order {
+                Order::Random => queue.get(rng.gen_range(0..queue.len())).cloned(),
+                _ => queue.pop_front(),
+            };
+        }
+    }
+
+    /// Stops picking `path` until the sources are scanned again.
+    fn discard(&mut self, path: &Path) {
+        for group in &mut self.groups {
+            group.queue.retain(|p| p != path);
+        }
+    }
+}
+
+/// Opens the next image from the sources, skipping files that cannot be decoded. `loader` is only
+/// locked while picking files, so several images can be decoded at once.
+pub fn load_next(loader: &Mutex<ImageLoader>) -> Result<(PathBuf, DynamicImage)> {
+    let lock = || loader.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut rescanned = HashSet::new();
+    loop {
+        let path = lock()
+            .next_path(&mut rescanned)
+            .context("Unable to open any file from the sources as an image")?;
+        info!("Attempting to load {}", path.display());
+        match image::open(&path) {
+            Ok(img) => {
+                info!("success");
+                return Ok((path, img));
+            }
+            Err(e) => {
+                warn!("Skipping {}: {e}", path.display());
+                lock().discard(&path);
+            }
+        }
+    }
+}
+
+/// Where a decoded image is going to be shown.
+#[derive(Clone, Copy, Debug)]
+pub struct Target {
+    pub scaling: Scaling,
+    /// Size of the output in physical pixels
+    pub size: (u32, u32),
+    /// Largest factor the image is zoomed in by while shown, see
+    /// [`KenBurns`](crate::config::KenBurns)
+    pub zoom: f32,
+    /// Largest width or height of a texture, if the GPU is known
+    pub max_dimension: Option<u32>,
+}
+
+impl Target {
+    /// Downscales `img` to the size it is shown at, so no memory is wasted on pixels that never
+    /// make it to the screen, and to fit into a texture.
+    pub fn fit(&self, img: DynamicImage) -> DynamicImage {
+        self.resized(&img).unwrap_or(img)
+    }
+
+    /// Like [`Target::fit`], but leaves `img` alone and returns `None` if it already fits.
+    pub fn resized(&self, img: &DynamicImage) -> Option<DynamicImage> {
+        // Zoomed in images show more of their pixels, keep enough to not upscale them
+        let size = (
+            (self.size.0 as f32 * self.zoom).ceil() as u32,
+            (self.size.1 as f32 * self.zoom).ceil() as u32,
+        );
+        let resized = self
+            .scaling
+            .shown_size(size, img.dimensions())
+            .map(|(width, height)| {
+                debug!(
+                    "Downscaling {}x{} image to {width}x{height}",
+                    img.width(),
+                    img.height()
+                );
+                img.resize_exact(width, height, FilterType::Lanczos3)
+            });
+        let current = resized.as_ref().unwrap_or(img);
+        match self.max_dimension {
+            Some(max) if current.width() > max || current.height() > max => {
+                debug!(
+                    "Downscaling {}x{} image to the texture size limit of {max}",
+                    current.width(),
+                    current.height()
+                );
+                Some(current.resize(max, max, FilterType::Lanczos3))
+            }
+            _ => resized,
+        }
+    }
+}
+
+pub fn load_path(path: &Path) -> Result<DynamicImage> {
+    image::open(path).with_context(|| format!("Unable to open {} as an image", path.display()))
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+/// Case insensitive glob patterns matched against paths relative to their source directory.
+/// Patterns starting with `!` exclude matching files, if there are no other patterns all files
+/// that are not excluded match.
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut has_include = false;
+        for pattern in patterns {
+            let (builder, glob) = match pattern.strip_prefix('!') {
+                Some(glob) => (&mut exclude, glob),
+                None => {
+                    has_include = true;
+                    (&mut include, pattern.as_str())
+                }
+            };
+            let glob = GlobBuilder::new(glob)
+                .literal_separator(true)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid glob pattern {pattern}"))?;
+            builder.add(glob);
+        }
+        Ok(Self {
+            include: has_include.then(|| include.build()).transpose()?,
+            exclude: exclude.build()?,
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|i| i.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::config::Collection;
+
+    fn filter(patterns: &[&str]) -> Filter {
+        let patterns: Vec<_> = patterns.iter().map(|p| p.to_string()).collect();
+        Filter::new(&patterns).unwrap()
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = filter(&[]);
+        assert!(filter.matches(Path::new("a.png")));
+        assert!(filter.matches(Path::new("dir/b.txt")));
+    }
+
+    #[test]
+    fn include_patterns() {
+        let filter = filter(&["*.png", "photos/**/*.jpg"]);
+        assert!(filter.matches(Path::new("a.png")));
+        assert!(filter.matches(Path::new("A.PNG")));
+        assert!(filter.matches(Path::new("photos/2024/b.jpg")));
+        assert!(!filter.matches(Path::new("b.jpg")));
+        // `*` does not cross directories
+        assert!(!filter.matches(Path::new("dir/a.png")));
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let filter = filter(&["!drafts/**", "!*.txt"]);
+        assert!(filter.matches(Path::new("a.png")));
+        assert!(!filter.matches(Path::new("notes.TXT")));
+        assert!(!filter.matches(Path::new("drafts/a.png")));
+
+        let filter = self::filter(&["**/*.png", "!drafts/**"]);
+        assert!(filter.matches(Path::new("final/a.png")));
+        assert!(!filter.matches(Path::new("drafts/a.png")));
+        assert!(!filter.matches(Path::new("final/a.jpg")));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(Filter::new(&["a[".to_string()]).is_err());
+    }
+
+    /// Temporary directory of empty files named `files`.
+    fn temp_dir(files: &[&str]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in files {
+            std::fs::write(dir.path().join(file), []).unwrap();
+        }
+        dir
+    }
+
+    fn loader(groups: &[(&TempDir, f32)], order: Order, reverse: bool) -> ImageLoader {
+        let groups = groups.iter().map(|(dir, weight)| Group {
+            paths: vec![dir.path().to_path_buf()],
+            weight: *weight,
+            queue: VecDeque::new(),
+        });
+        ImageLoader {
+            groups: groups.collect(),
+            scan: Scan::default(),
+            filter: filter(&[]),
+            order,
+            reverse,
+        }
+    }
+
+    /// File names of the next `n` paths picked, each looked for from scratch like in
+    /// [`load_next`].
+    fn next_names(loader: &mut ImageLoader, n: usize) -> Vec<String> {
+        (0..n)
+            .map(|_| {
+                let path = loader.next_path(&mut HashSet::new()).unwrap();
+                path.file_name().unwrap().to_string_lossy().into_owned()
             })
+            .collect()
     }
-    pub fn load_fade(&mut self) -> Result<Fade> {
-        let current_img =
-            std::mem::take(&mut self.current_img).context("Previous image not set yet")?;
-        let next_img = self.get_next_img()?;
-        self.current_img = Some(next_img.clone());
-        Ok(Fade::new(Duration::from_secs(5), 24, next_img, current_img))
+
+    #[test]
+    fn shuffle_shows_every_file_once_per_round() {
+        let dir = temp_dir(&["a", "b", "c"]);
+        let mut loader = loader(&[(&dir, 1.0)], Order::Shuffle, false);
+        for _ in 0..3 {
+            let mut names = next_names(&mut loader, 3);
+            names.sort();
+            assert_eq!(names, ["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn name_order() {
+        let dir = temp_dir(&["b", "a", "c"]);
+        let mut loader = loader(&[(&dir, 1.0)], Order::Name, false);
+        assert_eq!(next_names(&mut loader, 4), ["a", "b", "c", "a"]);
+
+        let mut reversed = self::loader(&[(&dir, 1.0)], Order::Name, true);
+        assert_eq!(next_names(&mut reversed, 4), ["c", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn refill_picks_up_new_files() {
+        let dir = temp_dir(&["a", "b"]);
+        let mut loader = loader(&[(&dir, 1.0)], Order::Name, false);
+        assert_eq!(next_names(&mut loader, 1), ["a"]);
+        // Files added while a queue is left are only seen once it is scanned again
+        std::fs::write(dir.path().join("c"), []).unwrap();
+        assert_eq!(next_names(&mut loader, 4), ["b", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn random_keeps_files() {
+        let dir = temp_dir(&["a", "b"]);
+        let mut loader = loader(&[(&dir, 1.0)], Order::Random, false);
+        for name in next_names(&mut loader, 10) {
+            assert!(name == "a" || name == "b");
+        }
+        assert_eq!(loader.groups[0].queue.len(), 2);
+    }
+
+    #[test]
+    fn weights_select_groups() {
+        let picked = temp_dir(&["a", "b"]);
+        let ignored = temp_dir(&["c"]);
+        let mut loader = loader(&[(&ignored, 0.0), (&picked, 2.0)], Order::Name, false);
+        assert_eq!(next_names(&mut loader, 3), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn skip_empty_groups() {
+        let empty = temp_dir(&[]);
+        let full = temp_dir(&["a"]);
+        let mut loader = loader(&[(&empty, 100.0), (&full, 1.0)], Order::Name, false);
+        assert_eq!(next_names(&mut loader, 2), ["a", "a"]);
+
+        let mut loader = self::loader(&[(&empty, 1.0)], Order::Shuffle, false);
+        assert_eq!(loader.next_path(&mut HashSet::new()), None);
     }
 
-    pub fn load_static(&mut self) -> Result<Static> {
-        let img = self.get_next_img()?;
-        self.current_img = Some(img.clone());
-        Ok(Static::new(img))
+    #[test]
+    fn reloading_keeps_the_position() {
+        let dirs: Vec<_> = (0..4).map(|_| temp_dir(&["a", "b"])).collect();
+        let config = || {
+            let collections = dirs.iter().enumerate().map(|(i, dir)| {
+                let collection = Collection {
+                    sources: vec![dir.path().to_path_buf()],
+                    weight: 1.0,
+                };
+                (i.to_string(), collection)
+            });
+            Config {
+                collections: collections.collect(),
+                ..Config::default()
+            }
+        };
+        let mut loader = ImageLoader::new(&config()).unwrap();
+        next_names(&mut loader, 1);
+        assert!(!loader.update(ImageLoader::new(&config()).unwrap()));
+
+        let reversed = Config {
+            reverse: true,
+            ..config()
+        };
+        assert!(loader.update(ImageLoader::new(&reversed).unwrap()));
+    }
+
+    #[test]
+    fn discarded_files_are_skipped() {
+        let dir = temp_dir(&["a", "b", "c"]);
+        let mut loader = loader(&[(&dir, 1.0)], Order::Name, false);
+        assert_eq!(next_names(&mut loader, 1), ["a"]);
+        loader.discard(&dir.path().join("b"));
+        // Until the next scan
+        assert_eq!(next_names(&mut loader, 3), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn resized_targets() {
+        use crate::render::placement::ScaleMode::*;
+
+        let cases = [
+            // Mode, target size, zoom, max dimension, image size, resized size
+            (Fill, (100, 50), 1.0, None, (400, 100), Some((200, 50))),
+            (Fill, (100, 50), 1.0, None, (100, 50), None),
+            (Fill, (100, 50), 1.0, None, (40, 20), None),
+            // Zoomed in images keep the pixels shown at the largest zoom
+            (Fill, (100, 50), 1.5, None, (400, 100), Some((300, 75))),
+            (Fill, (100, 50), 4.0, None, (400, 100), None),
+            (Fit, (100, 50), 1.0, None, (400, 400), Some((50, 50))),
+            (Stretch, (100, 50), 1.0, None, (400, 400), Some((100, 50))),
+            (Stretch, (100, 50), 1.0, None, (400, 20), Some((100, 20))),
+            (Center, (100, 50), 1.0, None, (400, 400), None),
+            (Tile, (100, 50), 1.0, None, (400, 400), None),
+            // Larger than a texture, keeping the aspect ratio
+            (Fill, (100, 50), 1.0, Some(64), (100, 50), Some((64, 32))),
+            (
+                Center,
+                (100, 50),
+                1.0,
+                Some(256),
+                (400, 100),
+                Some((256, 64)),
+            ),
+            (Fit, (100, 50), 1.0, Some(64), (400, 400), Some((50, 50))),
+            (Fill, (100, 50), 1.0, Some(256), (40, 20), None),
+        ];
+        for (mode, size, zoom, max_dimension, image, expected) in cases {
+            let target = Target {
+                scaling: Scaling {
+                    mode,
+                    ..Scaling::default()
+                },
+                size,
+                zoom,
+                max_dimension,
+            };
+            let img = DynamicImage::new_rgba8(image.0, image.1);
+            let resized = target.resized(&img).map(|img| img.dimensions());
+            assert_eq!(resized, expected, "{target:?} with {image:?}");
+            assert_eq!(
+                target.fit(img).dimensions(),
+                expected.unwrap_or(image),
+                "{target:?} with {image:?}"
+            );
+        }
     }
 }
@@ -0,0 +1,417 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction,
+};
+
+use crate::app::App;
+
+/// A command sent to the daemon over the control socket, one per line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Next,
+    Previous,
+    Pause,
+    Resume,
+    Set(PathBuf),
+    Current,
+    Status,
+}
+
+impl FromStr for Request {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (cmd, arg) = match s.trim().split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, Some(arg.trim())),
+            None => (s.trim(), None),
+        };
+        let request = match (cmd, arg) {
+            ("next", None) => Self::Next,
+            ("previous" | "prev", None) => Self::Previous,
+            ("pause", None) => Self::Pause,
+            ("resume", None) => Self::Resume,
+            ("set", Some(path)) => Self::Set(PathBuf::from(path)),
+            ("current", None) => Self::Current,
+            ("status", None) => Self::Status,
+            ("set", None) => bail!("set requires a path"),
+            (cmd, Some(_)) if !cmd.is_empty() => bail!("{cmd} does not take an argument"),
+            (cmd, _) => bail!("Unknown command \"{cmd}\""),
+        };
+        Ok(request)
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Next => write!(f, "next"),
+            Self::Previous => write!(f, "previous"),
+            Self::Pause => write!(f, "pause"),
+            Self::Resume => write!(f, "resume"),
+            Self::Set(path) => write!(f, "set {}", path.display()),
+            Self::Current => write!(f, "current"),
+            Self::Status => write!(f, "status"),
+        }
+    }
+}
+
+/// The reply to a [`Request`], sent back as a single line of JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    /// Image currently shown on each output, keyed by output name
+    Current {
+        outputs: BTreeMap<String, Option<PathBuf>>,
+    },
+    Status {
+        paused: bool,
+        /// Keyed by output name
+        outputs: BTreeMap<String, OutputStatus>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// State of a single output, see [`Response::Status`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputStatus {
+    /// Image currently shown
+    pub image: Option<PathBuf>,
+    /// Seconds between image switches, which may be overridden for the output
+    pub interval: u64,
+}
+
+impl Response {
+    pub fn error(e: impl fmt::Display) -> Self {
+        Self::Error {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Path of the control socket for the current wayland display.
+pub fn socket_path() -> Result<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").context("XDG_RUNTIME_DIR is not set")?;
+    let display = std::env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string());
+    Ok(PathBuf::from(runtime_dir).join(format!("wallswitcher-{display}.sock")))
+}
+
+/// Sends `request` to the running daemon and waits for its reply.
+pub fn send(request: &Request) -> Result<Response> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "Failed to connect to {}, is the daemon running?",
+            path.display()
+        )
+    })?;
+    writeln!(stream, "{request}")?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).with_context(|| format!("Invalid reply from daemon: {line:?}"))
+}
+
+/// Removes the control socket when dropped.
+pub struct SocketGuard {
+    path: PathBuf,
+}
+
+impl Drop for SocketGuard {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Binds the control socket and inserts it into the event loop. Requests are handled by
+/// [`App::handle_request`] until the returned guard is dropped.
+pub fn listen(handle: &LoopHandle<'static, App>) -> Result<SocketGuard> {
+    let path = socket_path()?;
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            bail!(
+                "Another instance is already listening on {}",
+                path.display()
+            );
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    listener.set_nonblocking(true)?;
+    let guard = SocketGuard { path };
+
+    let client_handle = handle.clone();
+    handle
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, _| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = accept(stream, &client_handle) {
+                                error!("Could not accept control connection: {e}");
+                            }
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        // Returning the error would stop the event loop
+                        Err(e) => {
+                            error!("Could not accept control connection: {e}");
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow!("{e}"))
+        .context("Failed to insert control socket into event loop")?;
+    info!("Listening on {}", guard.path.display());
+    Ok(guard)
+}
+
+fn accept(stream: UnixStream, handle: &LoopHandle<'static, App>) -> Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut client = Client::default();
+    handle
+        .insert_source(
+            // Edge triggered, as the socket is writable most of the time
+            Generic::new(stream, Interest::BOTH, Mode::Edge),
+            move |_, stream, app| {
+                let result = client.process(stream, |request| {
+                    debug!("Received request: {request}");
+                    app.handle_request(request)
+                });
+                Ok(match result {
+                    Ok(()) if client.is_done() => PostAction::Remove,
+                    Ok(()) => PostAction::Continue,
+                    Err(e) => {
+                        warn!("Dropping control connection: {e}");
+                        PostAction::Remove
+                    }
+                })
+            },
+        )
+        .map_err(|e| anyhow!("{e}"))?;
+    Ok(())
+}
+
+/// State of a connection to the control socket. Replies are queued rather than written with
+/// blocking calls, so that a client that does not read them cannot stall the event loop.
+#[derive(Default)]
+struct Client {
+    /// Received bytes that do not form a complete line yet
+    input: Vec<u8>,
+    /// Replies not written yet
+    output: Vec<u8>,
+    /// Whether the client shut down writing
+    closed: bool,
+}
+
+impl Client {
+    /// Writes pending replies, then reads requests and answers them with `handle` as long as
+    /// nothing is left to write, so the queue does not grow while the client is not reading.
+    fn process(
+        &mut self,
+        mut stream: &UnixStream,
+        mut handle: impl FnMut(Request) -> Response,
+    ) -> io::Result<()> {
+        self.flush(stream)?;
+        if !self.output.is_empty() {
+            return Ok(());
+        }
+        let mut chunk = [0; 1024];
+        loop {
+            match stream.read(&mut chunk) {
+                // Clients shut down writing right after their request, so it usually arrives
+                // together with the end of the stream
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        while let Some(end) = self.input.iter().position(|&b| b == b'\n') {
+            let line: Vec<_> = self.input.drain(..=end).collect();
+            let response = match std::str::from_utf8(&line)
+                .map_err(anyhow::Error::from)
+                .and_then(Request::from_str)
+            {
+                Ok(request) => handle(request),
+                Err(e) => Response::error(e),
+            };
+            serde_json::to_writer(&mut self.output, &response)?;
+            self.output.push(b'\n');
+        }
+        self.flush(stream)
+    }
+
+    fn flush(&mut self, mut stream: &UnixStream) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match stream.write(&self.output) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.output.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the client is gone and all replies are written.
+    fn is_done(&self) -> bool {
+        self.closed && self.output.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = [
+            Request::Next,
+            Request::Previous,
+            Request::Pause,
+            Request::Resume,
+            Request::Set(PathBuf::from("/images/with space.png")),
+            Request::Current,
+            Request::Status,
+        ];
+        for request in requests {
+            assert_eq!(request.to_string().parse::<Request>().unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!("prev".parse::<Request>().unwrap(), Request::Previous);
+        assert_eq!("  next \n".parse::<Request>().unwrap(), Request::Next);
+        assert_eq!(
+            "set   /a.png ".parse::<Request>().unwrap(),
+            Request::Set(PathBuf::from("/a.png"))
+        );
+    }
+
+    /// A connected client and server socket, the server side non-blocking like in [`accept`].
+    fn socket_pair() -> (UnixStream, UnixStream) {
+        let (client, server) = UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (client, server)
+    }
+
+    fn replies(client: &UnixStream) -> Vec<Response> {
+        BufReader::new(client)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn answer_requests_before_end_of_stream() {
+        let (mut client, server) = socket_pair();
+        client.write_all(b"pause\njump\nresume\n").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let mut handled = Vec::new();
+        let mut state = Client::default();
+        state
+            .process(&server, |request| {
+                handled.push(request);
+                Response::Ok
+            })
+            .unwrap();
+        assert_eq!(handled, [Request::Pause, Request::Resume]);
+        assert!(state.is_done());
+
+        drop(server);
+        assert_eq!(
+            replies(&client),
+            [
+                Response::Ok,
+                Response::error("Unknown command \"jump\""),
+                Response::Ok
+            ]
+        );
+    }
+
+    #[test]
+    fn wait_for_complete_lines() {
+        let (mut client, server) = socket_pair();
+        let mut state = Client::default();
+        client.write_all(b"nex").unwrap();
+        state.process(&server, |_| unreachable!()).unwrap();
+        assert!(!state.is_done());
+
+        client.write_all(b"t\n").unwrap();
+        let mut handled = Vec::new();
+        state
+            .process(&server, |request| {
+                handled.push(request);
+                Response::Ok
+            })
+            .unwrap();
+        assert_eq!(handled, [Request::Next]);
+        assert!(!state.is_done());
+    }
+
+    #[test]
+    fn queue_replies_of_clients_not_reading() {
+        let (mut client, server) = socket_pair();
+        let large = Response::error("x".repeat(1 << 20));
+        let handled = std::cell::Cell::new(0);
+        let mut handle = |_| {
+            handled.set(handled.get() + 1);
+            large.clone()
+        };
+        let mut state = Client::default();
+        client.write_all(b"status\n").unwrap();
+        state.process(&server, &mut handle).unwrap();
+        assert!(!state.output.is_empty());
+
+        // Further requests wait until the reply is read
+        client.write_all(b"status\n").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        state.process(&server, &mut handle).unwrap();
+        assert_eq!(handled.get(), 1);
+
+        let reader = std::thread::spawn(move || replies(&client));
+        while !state.is_done() {
+            state.process(&server, &mut handle).unwrap();
+        }
+        drop(server);
+        assert_eq!(handled.get(), 2);
+        assert_eq!(reader.join().unwrap(), [large.clone(), large]);
+    }
+
+    #[test]
+    fn reject_invalid_requests() {
+        for line in ["", "set", "next now", "jump", "Next"] {
+            assert!(line.parse::<Request>().is_err(), "{line:?}");
+        }
+    }
+}
@@ -1,8 +1,20 @@
 mod app;
 mod cli;
+mod config;
+mod frame_clock;
+mod image_loader;
+mod ipc;
 mod render;
+mod wallpaper;
 
-fn main() {
+use anyhow::Result;
+
+use cli::Action;
+
+fn main() -> Result<()> {
     env_logger::init();
-    app::App::run().expect("Failed to run app");
+    match cli::Cli::parse_and_validate()? {
+        Action::Daemon { config, overrides } => app::App::run(config, overrides),
+        Action::Request { request, json } => cli::print_response(&ipc::send(&request)?, json),
+    }
 }
@@ -0,0 +1,256 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use keyframe::functions::{
+    EaseIn, EaseInCubic, EaseInOut, EaseInOutCubic, EaseInOutQuad, EaseInOutQuart, EaseInOutQuint,
+    EaseInQuad, EaseInQuart, EaseInQuint, EaseOut, EaseOutCubic, EaseOutQuad, EaseOutQuart,
+    EaseOutQuint, Linear,
+};
+use serde::Deserialize;
+
+/// Curve mapping the linear progress of a transition onto its eased progress, written as one of
+/// the [`Easing::NAMED`] curves or as `cubic-bezier(x1, y1, x2, y2)` like in CSS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+    EaseInQuad,
+    EaseOutQuad,
+    EaseInOutQuad,
+    EaseInCubic,
+    EaseOutCubic,
+    EaseInOutCubic,
+    EaseInQuart,
+    EaseOutQuart,
+    EaseInOutQuart,
+    EaseInQuint,
+    EaseOutQuint,
+    EaseInOutQuint,
+    CubicBezier(CubicBezier),
+}
+
+impl Easing {
+    /// Curves available by name.
+    pub const NAMED: [(&'static str, Self); 16] = [
+        ("linear", Self::Linear),
+        ("ease-in", Self::EaseIn),
+        ("ease-out", Self::EaseOut),
+        ("ease-in-out", Self::EaseInOut),
+        ("ease-in-quad", Self::EaseInQuad),
+        ("ease-out-quad", Self::EaseOutQuad),
+        ("ease-in-out-quad", Self::EaseInOutQuad),
+        ("ease-in-cubic", Self::EaseInCubic),
+        ("ease-out-cubic", Self::EaseOutCubic),
+        ("ease-in-out-cubic", Self::EaseInOutCubic),
+        ("ease-in-quart", Self::EaseInQuart),
+        ("ease-out-quart", Self::EaseOutQuart),
+        ("ease-in-out-quart", Self::EaseInOutQuart),
+        ("ease-in-quint", Self::EaseInQuint),
+        ("ease-out-quint", Self::EaseOutQuint),
+        ("ease-in-out-quint", Self::EaseInOutQuint),
+    ];
+
+    /// Maps linear progress `t` in `[0, 1]` onto the curve.
+    pub fn ease(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let eased = match self {
+            Self::Linear => keyframe::ease(Linear, 0.0, 1.0, t),
+            Self::EaseIn => keyframe::ease(EaseIn, 0.0, 1.0, t),
+            Self::EaseOut => keyframe::ease(EaseOut, 0.0, 1.0, t),
+            Self::EaseInOut => keyframe::ease(EaseInOut, 0.0, 1.0, t),
+            Self::EaseInQuad => keyframe::ease(EaseInQuad, 0.0, 1.0, t),
+            Self::EaseOutQuad => keyframe::ease(EaseOutQuad, 0.0, 1.0, t),
+            Self::EaseInOutQuad => keyframe::ease(EaseInOutQuad, 0.0, 1.0, t),
+            Self::EaseInCubic => keyframe::ease(EaseInCubic, 0.0, 1.0, t),
+            Self::EaseOutCubic => keyframe::ease(EaseOutCubic, 0.0, 1.0, t),
+            Self::EaseInOutCubic => keyframe::ease(EaseInOutCubic, 0.0, 1.0, t),
+            Self::EaseInQuart => keyframe::ease(EaseInQuart, 0.0, 1.0, t),
+            Self::EaseOutQuart => keyframe::ease(EaseOutQuart, 0.0, 1.0, t),
+            Self::EaseInOutQuart => keyframe::ease(EaseInOutQuart, 0.0, 1.0, t),
+            Self::EaseInQuint => keyframe::ease(EaseInQuint, 0.0, 1.0, t),
+            Self::EaseOutQuint => keyframe::ease(EaseOutQuint, 0.0, 1.0, t),
+            Self::EaseInOutQuint => keyframe::ease(EaseInOutQuint, 0.0, 1.0, t),
+            Self::CubicBezier(curve) => return curve.y(t),
+        };
+        eased as f32
+    }
+}
+
+impl TryFrom<String> for Easing {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl FromStr for Easing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some((_, easing)) = Self::NAMED.iter().find(|(name, _)| *name == s) {
+            return Ok(*easing);
+        }
+        if let Some(points) = s
+            .strip_prefix("cubic-bezier(")
+            .and_then(|s| s.strip_suffix(')'))
+        {
+            return CubicBezier::new(points).map(Self::CubicBezier);
+        }
+        let names: Vec<_> = Self::NAMED.iter().map(|(name, _)| *name).collect();
+        bail!(
+            "Unknown easing {s}, expected one of {} or cubic-bezier(x1, y1, x2, y2)",
+            names.join(", ")
+        )
+    }
+}
+
+/// Cubic Bézier curve from (0, 0) to (1, 1) with the control points (x1, y1) and (x2, y2).
+///
+/// Solved here rather than with keyframe's `BezierCurve`, whose lookup table is sampled at the
+/// wrong points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CubicBezier {
+    x1: f32,
+    y1: f32,
+    x2: f32,
+    y2: f32,
+}
+
+impl CubicBezier {
+    /// Parses the comma separated control points `x1, y1, x2, y2`.
+    fn new(points: &str) -> Result<Self> {
+        let points = points
+            .split(',')
+            .map(|p| p.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Invalid control points cubic-bezier({points})"))?;
+        let &[x1, y1, x2, y2] = points.as_slice() else {
+            bail!("cubic-bezier needs 4 values, got {}", points.len());
+        };
+        if points.iter().any(|p| !p.is_finite()) {
+            bail!("cubic-bezier values must be finite");
+        }
+        if !(0.0..=1.0).contains(&x1) || !(0.0..=1.0).contains(&x2) {
+            bail!("cubic-bezier x values must be between 0 and 1");
+        }
+        Ok(Self { x1, y1, x2, y2 })
+    }
+
+    /// Height of the curve at `x`.
+    fn y(self, x: f32) -> f32 {
+        const EPSILON: f32 = 1e-6;
+        // x grows monotonically with the curve parameter as x1 and x2 are in [0, 1], try Newton's
+        // method first and fall back to bisection where it does not converge
+        let mut t = x;
+        for _ in 0..8 {
+            let error = bezier(t, self.x1, self.x2) - x;
+            if error.abs() < EPSILON {
+                return bezier(t, self.y1, self.y2);
+            }
+            let slope = bezier_slope(t, self.x1, self.x2);
+            if slope.abs() < EPSILON {
+                break;
+            }
+            t = (t - error / slope).clamp(0.0, 1.0);
+        }
+        let (mut low, mut high) = (0.0, 1.0);
+        t = x;
+        for _ in 0..32 {
+            let error = bezier(t, self.x1, self.x2) - x;
+            if error.abs() < EPSILON {
+                break;
+            }
+            if error < 0.0 {
+                low = t;
+            } else {
+                high = t;
+            }
+            t = (low + high) / 2.0;
+        }
+        bezier(t, self.y1, self.y2)
+    }
+}
+
+/// One coordinate of a cubic Bézier curve from 0 to 1 with the control points `p1` and `p2`.
+fn bezier(t: f32, p1: f32, p2: f32) -> f32 {
+    let u = 1.0 - t;
+    3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t
+}
+
+fn bezier_slope(t: f32, p1: f32, p2: f32) -> f32 {
+    let u = 1.0 - t;
+    3.0 * u * u * p1 + 6.0 * u * t * (p2 - p1) + 3.0 * t * t * (1.0 - p2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_named() {
+        for (name, easing) in Easing::NAMED {
+            assert_eq!(name.parse::<Easing>().unwrap(), easing);
+        }
+        assert!("ease".parse::<Easing>().is_err());
+        assert!("Linear".parse::<Easing>().is_err());
+    }
+
+    #[test]
+    fn parse_cubic_bezier() {
+        assert_eq!(
+            "cubic-bezier(0.25, -0.5, 1, 1.5)"
+                .parse::<Easing>()
+                .unwrap(),
+            Easing::CubicBezier(CubicBezier {
+                x1: 0.25,
+                y1: -0.5,
+                x2: 1.0,
+                y2: 1.5,
+            })
+        );
+        for invalid in [
+            "cubic-bezier(0.1, 0.2, 0.3)",
+            "cubic-bezier(0.1, 0.2, 0.3, 0.4, 0.5)",
+            "cubic-bezier(0.1, a, 0.3, 0.4)",
+            "cubic-bezier(1.5, 0, 0.5, 1)",
+            "cubic-bezier(0, inf, 0.5, 1)",
+            "cubic-bezier(0, 0, 0.5, 1",
+        ] {
+            assert!(invalid.parse::<Easing>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn curves_start_at_0_and_end_at_1() {
+        let curves = Easing::NAMED.iter().map(|(_, easing)| *easing).chain([
+            "cubic-bezier(0.25, 0.1, 0.25, 1)".parse().unwrap(),
+            "cubic-bezier(0.7, -0.6, 0.3, 1.6)".parse().unwrap(),
+            "cubic-bezier(0, 1, 1, 0)".parse().unwrap(),
+        ]);
+        for easing in curves {
+            assert!(easing.ease(0.0).abs() < 1e-4, "{easing:?}");
+            assert!((easing.ease(1.0) - 1.0).abs() < 1e-4, "{easing:?}");
+            // Progress outside of [0, 1] is clamped
+            assert!(easing.ease(-1.0).abs() < 1e-4, "{easing:?}");
+            assert!((easing.ease(2.0) - 1.0).abs() < 1e-4, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn cubic_bezier_values() {
+        let linear = CubicBezier::new("0.5, 0.5, 0.5, 0.5").unwrap();
+        for x in [0.1, 0.3, 0.5, 0.9] {
+            assert!((linear.y(x) - x).abs() < 1e-4);
+        }
+        // CSS `ease-in` is below the diagonal, symmetric curves pass through the middle
+        let ease_in = CubicBezier::new("0.42, 0, 1, 1").unwrap();
+        assert!(ease_in.y(0.5) < 0.5);
+        let ease_in_out = CubicBezier::new("0.42, 0, 0.58, 1").unwrap();
+        assert!((ease_in_out.y(0.5) - 0.5).abs() < 1e-4);
+    }
+}
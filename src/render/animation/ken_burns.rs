@@ -0,0 +1,127 @@
+use std::time::{Duration, Instant};
+
+use image::DynamicImage;
+use rand::Rng;
+
+use crate::{
+    config,
+    render::{Context, Framing, Scaling},
+};
+
+use super::{Animation, Easing};
+
+/// Slowly pans and zooms the images of another animation while they are shown, including during
+/// transitions between them.
+pub struct KenBurns {
+    inner: Box<dyn Animation>,
+    settings: config::KenBurns,
+    interval: Duration,
+    /// Motion of the previous image, still visible while transitioning away from it
+    previous: Motion,
+    current: Motion,
+}
+
+/// Movement of a single image from one framing to another, starting when it is first shown.
+#[derive(Clone, Copy)]
+struct Motion {
+    start: Instant,
+    from: Framing,
+    to: Framing,
+}
+
+impl KenBurns {
+    /// Moves the images of `inner` according to `settings`, one image is shown for `interval`.
+    pub fn new(inner: Box<dyn Animation>, settings: config::KenBurns, interval: Duration) -> Self {
+        let current = Motion::new(&settings);
+        Self {
+            inner,
+            settings,
+            interval,
+            previous: current,
+            current,
+        }
+    }
+
+    /// Framing at `time` of an image moving with `motion`.
+    fn framing(&self, motion: &Motion, time: Instant) -> Framing {
+        let elapsed = time.saturating_duration_since(motion.start).as_secs_f32();
+        let interval = self.interval.as_secs_f32().max(f32::EPSILON);
+        motion.framing(elapsed / interval * self.settings.speed)
+    }
+}
+
+impl Motion {
+    fn new(settings: &config::KenBurns) -> Self {
+        let [min, max] = settings.zoom;
+        let center = Framing::default().focus;
+        if !settings.random {
+            return Self {
+                start: Instant::now(),
+                from: Framing {
+                    zoom: min,
+                    focus: center,
+                },
+                to: Framing {
+                    zoom: max,
+                    focus: center,
+                },
+            };
+        }
+        let mut rng = rand::thread_rng();
+        let (from_zoom, to_zoom) = if rng.gen() { (min, max) } else { (max, min) };
+        let mut focus = || [rng.gen_range(0.0..=1.0), rng.gen_range(0.0..=1.0)];
+        Self {
+            start: Instant::now(),
+            from: Framing {
+                zoom: from_zoom,
+                focus: focus(),
+            },
+            to: Framing {
+                zoom: to_zoom,
+                focus: focus(),
+            },
+        }
+    }
+
+    /// Framing after moving for `sweeps` times the distance from `from` to `to`, turning around
+    /// at either end.
+    fn framing(&self, sweeps: f32) -> Framing {
+        let bounce = 1.0 - (1.0 - sweeps.rem_euclid(2.0)).abs();
+        let t = Easing::EaseInOut.ease(bounce);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Framing {
+            zoom: lerp(self.from.zoom, self.to.zoom),
+            focus: [
+                lerp(self.from.focus[0], self.to.focus[0]),
+                lerp(self.from.focus[1], self.to.focus[1]),
+            ],
+        }
+    }
+}
+
+impl Animation for KenBurns {
+    fn render(&mut self, ctx: &Context, time: Instant) -> bool {
+        let from = self.framing(&self.previous, time);
+        let to = self.framing(&self.current, time);
+        self.inner.set_framing(from, to);
+        self.inner.render(ctx, time)
+    }
+
+    fn update_img(&mut self, img: &DynamicImage, ctx: &Context) {
+        self.previous = std::mem::replace(&mut self.current, Motion::new(&self.settings));
+        self.inner.update_img(img, ctx);
+    }
+
+    fn is_finished(&self) -> bool {
+        // Always moving
+        false
+    }
+
+    fn set_timing(&mut self, duration: Duration, easing: Easing) {
+        self.inner.set_timing(duration, easing);
+    }
+
+    fn set_scaling(&mut self, scaling: Scaling, ctx: &Context) {
+        self.inner.set_scaling(scaling, ctx);
+    }
+}
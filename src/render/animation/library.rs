@@ -0,0 +1,258 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::*;
+use naga::{
+    front::glsl,
+    valid::{Capabilities, ValidationFlags, Validator},
+    ShaderStage, Span,
+};
+
+use crate::config::TransitionKind;
+
+use super::{PLACEMENT_WGSL, VERTEX_WGSL};
+
+const TRANSITION_WGSL: &str = include_str!("./shaders/transition.wgsl");
+const GL_TRANSITION_GLSL: &str = include_str!("./shaders/gl_transition.glsl");
+const GL_TRANSITION_MAIN_GLSL: &str = include_str!("./shaders/gl_transition_main.glsl");
+
+/// Transition shaders by name. Besides the built in ones, every `.wgsl` file in the transitions
+/// directory defining a transition as described in `transition.wgsl`, and every `.glsl` file from
+/// gl-transitions.com, is available under its file name.
+#[derive(Debug, Default, PartialEq)]
+pub struct ShaderLibrary {
+    custom: BTreeMap<String, Custom>,
+}
+
+/// Source of a custom transition as read from its file.
+#[derive(Debug, PartialEq)]
+enum Custom {
+    Wgsl(String),
+    /// Uniforms already replaced by constants
+    Glsl(String),
+}
+
+/// Complete shader of a transition.
+pub enum Shader {
+    /// Module with both `vs_main` and `fs_main`
+    Wgsl(String),
+    /// Fragment stage with entry point `main`, used with `vertex.wgsl`
+    Glsl(String),
+}
+
+impl ShaderLibrary {
+    /// Reads the shaders in `dir`. Shaders that fail to compile are reported and left out.
+    pub fn load(dir: &Path) -> Self {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Could not read transitions from {}: {e}", dir.display());
+                }
+                return Self::default();
+            }
+        };
+        let mut custom = BTreeMap::new();
+        let paths = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "wgsl" || e == "glsl"));
+        for path in paths {
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if !matches!(
+                TransitionKind::from(name.to_string()),
+                TransitionKind::Custom(_)
+            ) {
+                warn!(
+                    "Ignoring {}, {name} is the name of a built in transition",
+                    path.display()
+                );
+                continue;
+            }
+            if custom.contains_key(name) {
+                warn!(
+                    "Ignoring {}, transition {name} was already loaded",
+                    path.display()
+                );
+                continue;
+            }
+            match read_shader(&path) {
+                Ok(shader) => {
+                    info!("Loaded transition {name} from {}", path.display());
+                    custom.insert(name.to_string(), shader);
+                }
+                Err(e) => error!("Ignoring transition {}:\n{e:#}", path.display()),
+            }
+        }
+        Self { custom }
+    }
+
+    /// Complete shader of `kind`, `None` if it has no shader or is unknown.
+    pub fn shader(&self, kind: &TransitionKind) -> Option<Shader> {
+        Some(match kind {
+            TransitionKind::Custom(name) => match self.custom.get(name)? {
+                Custom::Wgsl(source) => Shader::Wgsl(complete_shader(source)),
+                Custom::Glsl(source) => Shader::Glsl(complete_glsl(source)),
+            },
+            kind => Shader::Wgsl(complete_shader(built_in_source(kind)?)),
+        })
+    }
+
+    /// Kinds [`TransitionKind::Random`] picks from.
+    pub fn pool(&self) -> Vec<TransitionKind> {
+        TransitionKind::BUILT_IN
+            .into_iter()
+            .chain(self.custom.keys().cloned().map(TransitionKind::Custom))
+            .collect()
+    }
+
+    /// Fails if any of `kinds` is a custom transition that is not in the library.
+    pub fn check<'a>(&self, kinds: impl IntoIterator<Item = &'a TransitionKind>) -> Result<()> {
+        for kind in kinds {
+            if let TransitionKind::Custom(name) = kind {
+                if !self.custom.contains_key(name) {
+                    bail!(
+                        "Unknown transition {name}, no valid shader {name}.wgsl or {name}.glsl \
+                         was found"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `transition` function of a shader followed by the shared helpers, placed last so that line
+/// numbers in errors match the file.
+fn complete_shader(source: &str) -> String {
+    format!("{source}\n{PLACEMENT_WGSL}\n{VERTEX_WGSL}\n{TRANSITION_WGSL}")
+}
+
+/// The transition of a GLSL shader between the wrapper providing its inputs and `main`.
+fn complete_glsl(source: &str) -> String {
+    format!("{GL_TRANSITION_GLSL}\n{source}\n{GL_TRANSITION_MAIN_GLSL}")
+}
+
+/// Reads the shader at `path` and checks that it compiles.
+fn read_shader(path: &Path) -> Result<Custom> {
+    let source = std::fs::read_to_string(path).context("Failed to read file")?;
+    let file = path.display().to_string();
+    if path.extension().is_some_and(|e| e == "glsl") {
+        return read_glsl(&source, &file).map(Custom::Glsl);
+    }
+    let shader = complete_shader(&source);
+    let module = naga::front::wgsl::parse_str(&shader)
+        .map_err(|e| anyhow!(e.emit_to_string_with_path(&shader, &file)))?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| anyhow!(e.emit_to_string_with_path(&shader, &file)))?;
+    Ok(Custom::Wgsl(source))
+}
+
+/// Converts the uniforms of a gl-transition and checks that it compiles.
+fn read_glsl(source: &str, file: &str) -> Result<String> {
+    let source = inline_uniforms(source, file)?;
+    let shader = complete_glsl(&source);
+    // Lines before the transition in `shader`, subtracted from the lines of errors
+    let offset = GL_TRANSITION_GLSL.lines().count() as u32 + 1;
+    let locate = |span: Span| {
+        let location = span.location(&shader);
+        match location.line_number.checked_sub(offset) {
+            Some(line) if line > 0 => format!("{file}:{line}:{}", location.line_position),
+            _ => file.to_string(),
+        }
+    };
+    let module = glsl::Frontend::default()
+        .parse(&glsl::Options::from(ShaderStage::Fragment), &shader)
+        .map_err(|e| {
+            let errors: Vec<_> = e
+                .errors
+                .iter()
+                .map(|e| format!("{}: {}", locate(e.meta), e.kind))
+                .collect();
+            anyhow!(errors.join("\n"))
+        })?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| {
+            let span = e.spans().next().map(|(span, _)| *span).unwrap_or_default();
+            anyhow!("{}: {:#}", locate(span), anyhow::Error::new(e.into_inner()))
+        })?;
+    Ok(source)
+}
+
+/// Replaces the uniforms of a gl-transition by constants of the default value in their comment,
+/// as in `uniform float size; // = 0.2` or `uniform ivec2 size /* = ivec2(4) */;`, keeping the
+/// line numbers.
+fn inline_uniforms(source: &str, file: &str) -> Result<String> {
+    let mut inlined = String::with_capacity(source.len());
+    for (i, line) in source.lines().enumerate() {
+        match line.trim_start().strip_prefix("uniform ") {
+            Some(uniform) => {
+                let Some((declaration, default)) = uniform.split_once('=') else {
+                    bail!("{file}:{}: uniform without a default value", i + 1);
+                };
+                let declaration = declaration
+                    .split([';', '/'])
+                    .next()
+                    .unwrap_or_default()
+                    .trim();
+                let default = default.split("*/").next().unwrap_or_default();
+                let default = default.trim().trim_end_matches(';').trim();
+                inlined.push_str(&format!("const {declaration} = {default};"));
+            }
+            None => inlined.push_str(line),
+        }
+        inlined.push('\n');
+    }
+    Ok(inlined)
+}
+
+fn built_in_source(kind: &TransitionKind) -> Option<&'static str> {
+    Some(match kind {
+        TransitionKind::Fade => include_str!("./shaders/transitions/fade.wgsl"),
+        TransitionKind::Wipe => include_str!("./shaders/transitions/wipe.wgsl"),
+        TransitionKind::Slide => include_str!("./shaders/transitions/slide.wgsl"),
+        TransitionKind::Zoom => include_str!("./shaders/transitions/zoom.wgsl"),
+        TransitionKind::Dissolve => include_str!("./shaders/transitions/dissolve.wgsl"),
+        TransitionKind::Circle => include_str!("./shaders/transitions/circle.wgsl"),
+        TransitionKind::Pixelate => include_str!("./shaders/transitions/pixelate.wgsl"),
+        TransitionKind::None | TransitionKind::Random | TransitionKind::Custom(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_uniform_defaults() {
+        let source = "\
+uniform float size; // = 0.2
+  uniform ivec2 squares /* = ivec2(4) */;
+uniform vec4 color; // = vec4(0.0, 0.0, 0.0, 1.0);
+vec4 transition(vec2 uv) {
+  return color;
+}";
+        let expected = "\
+const float size = 0.2;
+const ivec2 squares = ivec2(4);
+const vec4 color = vec4(0.0, 0.0, 0.0, 1.0);
+vec4 transition(vec2 uv) {
+  return color;
+}
+";
+        assert_eq!(inline_uniforms(source, "test.glsl").unwrap(), expected);
+    }
+
+    #[test]
+    fn uniform_without_default() {
+        let source = "// A transition\nuniform float size;\n";
+        let e = inline_uniforms(source, "test.glsl").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "test.glsl:2: uniform without a default value"
+        );
+    }
+}
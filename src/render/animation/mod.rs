@@ -1,15 +1,31 @@
-use super::{context::Context, Texture};
+use super::{context::Context, transform, Framing, Scaling, Texture};
 
-mod fade;
+use std::time::{Duration, Instant};
+
+mod easing;
+mod ken_burns;
+mod library;
 mod r#static;
-pub use fade::Fade;
+mod transition;
+pub use easing::Easing;
 use image::DynamicImage;
+pub use ken_burns::KenBurns;
+pub use library::{Shader, ShaderLibrary};
 pub use r#static::Static;
+use smithay_client_toolkit::reexports::client::protocol::wl_output;
+pub use transition::Transition;
 use wgpu::util::DeviceExt;
 pub trait Animation {
-    fn render(&mut self, ctx: &Context);
+    /// Draws the frame shown at `time`, returning whether it was presented.
+    fn render(&mut self, ctx: &Context, time: Instant) -> bool;
     fn update_img(&mut self, img: &DynamicImage, ctx: &Context);
     fn is_finished(&self) -> bool;
+    /// Changes the length and easing of transitions. Ignored by animations without transitions.
+    fn set_timing(&mut self, _duration: Duration, _easing: Easing) {}
+    fn set_scaling(&mut self, scaling: Scaling, ctx: &Context);
+    /// Shows only parts of the image transitioned from and the one shown or transitioned to,
+    /// see [`KenBurns`]. Ignored by animations without placed images.
+    fn set_framing(&mut self, _from: Framing, _to: Framing) {}
 }
 
 #[repr(C)]
@@ -61,6 +77,31 @@ pub const VERTICES: &[Vertex] = &[
 
 pub const INDICES: &[u16] = &[0, 1, 3, 2, 3, 1];
 
+/// [`VERTICES`] of a buffer with `transform`. Each corner stays in place and gets the texture
+/// coordinates of the point of the surface shown there, so that shaders keep working in the
+/// orientation of the surface.
+pub fn transformed_vertices(transform: wl_output::Transform) -> Vec<Vertex> {
+    VERTICES
+        .iter()
+        .map(|vertex| Vertex {
+            position: vertex.position,
+            tex_coords: transform::surface_coords(vertex.tex_coords, transform),
+        })
+        .collect()
+}
+
+const PLACEMENT_WGSL: &str = include_str!("./shaders/placement.wgsl");
+const VERTEX_WGSL: &str = include_str!("./shaders/vertex.wgsl");
+
+/// Compiles an animation shader with the helpers of `placement.wgsl` prepended.
+pub fn create_shader(ctx: &Context, source: &str) -> wgpu::ShaderModule {
+    ctx.device()
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(format!("{PLACEMENT_WGSL}\n{source}").into()),
+        })
+}
+
 pub fn create_vertex_buffer(ctx: &Context) -> wgpu::Buffer {
     ctx.device()
         .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -82,36 +123,50 @@ pub fn create_texture_binds(
     textures: &[&Texture],
     ctx: &Context,
 ) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
-    let device = ctx.device();
-    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: (0..textures.len())
-            .flat_map(|i| {
-                [
-                    wgpu::BindGroupLayoutEntry {
-                        binding: (i * 2) as u32,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
+    let layout = create_texture_bind_group_layout(textures.len(), ctx);
+    let bind_group = create_texture_bind_group(&layout, textures, ctx);
+    (layout, bind_group)
+}
+
+/// Layout of `count` textures, each followed by its sampler.
+pub fn create_texture_bind_group_layout(count: usize, ctx: &Context) -> wgpu::BindGroupLayout {
+    ctx.device()
+        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: (0..count)
+                .flat_map(|i| {
+                    [
+                        wgpu::BindGroupLayoutEntry {
+                            binding: (i * 2) as u32,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
                         },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: (i * 2 + 1) as u32,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ]
-            })
-            .collect::<Vec<_>>()
-            .as_slice(),
-    });
+                        wgpu::BindGroupLayoutEntry {
+                            binding: (i * 2 + 1) as u32,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ]
+                })
+                .collect::<Vec<_>>()
+                .as_slice(),
+        })
+}
 
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &layout,
+/// Binds `textures` with a layout from [`create_texture_bind_group_layout`].
+pub fn create_texture_bind_group(
+    layout: &wgpu::BindGroupLayout,
+    textures: &[&Texture],
+    ctx: &Context,
+) -> wgpu::BindGroup {
+    ctx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
         entries: textures
             .iter()
             .enumerate()
@@ -130,8 +185,17 @@ pub fn create_texture_binds(
             .collect::<Vec<_>>()
             .as_slice(),
         label: None,
-    });
-    (layout, bind_group)
+    })
+}
+
+/// Binds each of `textures` followed by its blurred copy, as expected by `sample_placed` in
+/// `placement.wgsl`.
+pub fn create_placed_texture_binds(
+    textures: &[&Texture],
+    ctx: &Context,
+) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+    let textures: Vec<_> = textures.iter().flat_map(|t| [*t, t.blurred()]).collect();
+    create_texture_binds(&textures, ctx)
 }
 
 pub fn create_uniform_binds(
@@ -145,8 +209,14 @@ pub fn create_uniform_binds(
         usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
     });
 
-    let layout = ctx
-        .device()
+    let layout = create_uniform_bind_group_layout(ctx);
+    let group = create_uniform_bind_group(&layout, &buffer, ctx);
+    (buffer, layout, group)
+}
+
+/// Layout of a single uniform buffer.
+pub fn create_uniform_bind_group_layout(ctx: &Context) -> wgpu::BindGroupLayout {
+    ctx.device()
         .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: None,
             entries: &[wgpu::BindGroupLayoutEntry {
@@ -159,25 +229,41 @@ pub fn create_uniform_binds(
                 },
                 count: None,
             }],
-        });
+        })
+}
 
-    let group = ctx.device().create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &layout,
+/// Binds `buffer` with a layout from [`create_uniform_bind_group_layout`].
+pub fn create_uniform_bind_group(
+    layout: &wgpu::BindGroupLayout,
+    buffer: &wgpu::Buffer,
+    ctx: &Context,
+) -> wgpu::BindGroup {
+    ctx.device().create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
         entries: &[wgpu::BindGroupEntry {
             binding: 0,
             resource: buffer.as_entire_binding(),
         }],
         label: None,
-    });
-
-    (buffer, layout, group)
+    })
 }
 
 pub fn create_pipeline(
     ctx: &Context,
     bind_group_layouts: &[&wgpu::BindGroupLayout],
     shader: &wgpu::ShaderModule,
-    config: &wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    create_split_pipeline(ctx, bind_group_layouts, shader, (shader, "fs_main"), format)
+}
+
+/// Like [`create_pipeline`], with the fragment stage taken from another module and entry point.
+pub fn create_split_pipeline(
+    ctx: &Context,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    vertex: &wgpu::ShaderModule,
+    fragment: (&wgpu::ShaderModule, &str),
+    format: wgpu::TextureFormat,
 ) -> wgpu::RenderPipeline {
     let layout = ctx
         .device()
@@ -192,16 +278,16 @@ pub fn create_pipeline(
             label: None,
             layout: Some(&layout),
             vertex: wgpu::VertexState {
-                module: shader,
+                module: vertex,
                 entry_point: "vs_main",
                 buffers: &[Vertex::desc()],
                 compilation_options: Default::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: shader,
-                entry_point: "fs_main",
+                module: fragment.0,
+                entry_point: fragment.1,
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
+                    format,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -0,0 +1,48 @@
+struct Params {
+  // Distance between taps in texture coordinates, zero to copy the texture
+  step: vec2<f32>,
+};
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) tex_coords: vec2<f32>,
+};
+struct VertexOutput {
+  @builtin(position) clip_position: vec4<f32>,
+  @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+  model: VertexInput,
+) -> VertexOutput {
+  var out: VertexOutput;
+  out.tex_coords = model.tex_coords;
+  out.clip_position = vec4<f32>(model.position, 1.0);
+  return out;
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(1) @binding(0)
+var<uniform> params: Params;
+
+const RADIUS: i32 = 12;
+const SIGMA: f32 = 6.0;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  if (all(params.step == vec2<f32>(0.0))) {
+    return textureSampleLevel(t_source, s_source, in.tex_coords, 0.0);
+  }
+  var color = vec4<f32>(0.0);
+  var total = 0.0;
+  for (var i = -RADIUS; i <= RADIUS; i++) {
+    let weight = exp(-f32(i * i) / (2.0 * SIGMA * SIGMA));
+    color += textureSampleLevel(t_source, s_source, in.tex_coords + params.step * f32(i), 0.0) * weight;
+    total += weight;
+  }
+  return color / total;
+}
@@ -0,0 +1,89 @@
+#version 450
+// Prepended to `.glsl` transitions in the format of gl-transitions.com, which are followed by
+// `gl_transition_main.glsl` and used as the fragment stage after `vertex.wgsl`.
+//
+// Such a transition defines `vec4 transition(vec2 uv)` with `uv` running from (0, 0) at the bottom
+// left to (1, 1) at the top right. It samples the images with `getFromColor` and `getToColor` and
+// reads `progress` and `ratio`, the width of the surface divided by its height. Its own uniforms
+// become constants of the default value in their comment, `uniform float size; // = 0.2` turns
+// into `const float size = 0.2;`.
+
+struct Placement {
+  vec2 scale;
+  vec2 offset;
+  vec4 background;
+  vec2 background_scale;
+  vec2 background_offset;
+  uint mode;
+  uint blur_background;
+};
+
+layout(location = 0) in vec2 v_tex_coords;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform texture2D from_view;
+layout(set = 0, binding = 1) uniform sampler from_sampler;
+layout(set = 0, binding = 2) uniform texture2D from_background_view;
+layout(set = 0, binding = 3) uniform sampler from_background_sampler;
+layout(set = 0, binding = 4) uniform texture2D to_view;
+layout(set = 0, binding = 5) uniform sampler to_sampler;
+layout(set = 0, binding = 6) uniform texture2D to_background_view;
+layout(set = 0, binding = 7) uniform sampler to_background_sampler;
+
+layout(set = 1, binding = 0) uniform TransitionParams {
+  float progress;
+  float ratio;
+  float _time;
+  float _seed;
+  vec2 _direction;
+  Placement _placement_from;
+  Placement _placement_to;
+};
+
+// Gradients of the surface coordinates, see `transition.wgsl`
+vec2 _surface_ddx;
+vec2 _surface_ddy;
+
+// Image coordinates of `uv` with the y axis flipped to point down, as in `placement.wgsl`
+vec2 _image_coords(Placement placement, vec2 uv) {
+  vec2 coords = vec2(uv.x, 1.0 - uv.y) * placement.scale + placement.offset;
+  return placement.mode == 2u ? fract(coords) : coords;
+}
+
+vec2 _background_coords(Placement placement, vec2 uv) {
+  return vec2(uv.x, 1.0 - uv.y) * placement.background_scale + placement.background_offset;
+}
+
+// Same as the end of `sample_placed_grad` in `placement.wgsl`
+vec4 _placed(Placement placement, vec2 uv, vec4 color, vec4 background) {
+  vec2 coords = vec2(uv.x, 1.0 - uv.y) * placement.scale + placement.offset;
+  bool outside = any(lessThan(coords, vec2(0.0))) || any(greaterThan(coords, vec2(1.0)));
+  if (placement.mode == 1u && outside) {
+    return placement.blur_background != 0u ? background : placement.background;
+  }
+  return color;
+}
+
+vec4 getFromColor(vec2 uv) {
+  vec4 color = textureGrad(
+    sampler2D(from_view, from_sampler), _image_coords(_placement_from, uv),
+    _surface_ddx * _placement_from.scale, _surface_ddy * _placement_from.scale
+  );
+  vec4 background = textureLod(
+    sampler2D(from_background_view, from_background_sampler),
+    _background_coords(_placement_from, uv), 0.0
+  );
+  return _placed(_placement_from, uv, color, background);
+}
+
+vec4 getToColor(vec2 uv) {
+  vec4 color = textureGrad(
+    sampler2D(to_view, to_sampler), _image_coords(_placement_to, uv),
+    _surface_ddx * _placement_to.scale, _surface_ddy * _placement_to.scale
+  );
+  vec4 background = textureLod(
+    sampler2D(to_background_view, to_background_sampler),
+    _background_coords(_placement_to, uv), 0.0
+  );
+  return _placed(_placement_to, uv, color, background);
+}
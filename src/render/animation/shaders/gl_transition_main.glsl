@@ -0,0 +1,6 @@
+
+void main() {
+  _surface_ddx = dFdx(v_tex_coords);
+  _surface_ddy = dFdy(v_tex_coords);
+  out_color = vec4(transition(vec2(v_tex_coords.x, 1.0 - v_tex_coords.y)).rgb, 1.0);
+}
@@ -0,0 +1,30 @@
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) tex_coords: vec2<f32>,
+};
+struct VertexOutput {
+  @builtin(position) clip_position: vec4<f32>,
+  @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+  model: VertexInput,
+) -> VertexOutput {
+  var out: VertexOutput;
+  out.tex_coords = model.tex_coords;
+  out.clip_position = vec4<f32>(model.position, 1.0);
+  return out;
+}
+
+// The previous mip level, only containing that level
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  // Sampling between four texels with a linear filter averages them
+  return textureSampleLevel(t_source, s_source, in.tex_coords, 0.0);
+}
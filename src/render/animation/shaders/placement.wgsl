@@ -0,0 +1,61 @@
+// Shared by all animations, prepended to their shaders
+
+struct Placement {
+  scale: vec2<f32>,
+  offset: vec2<f32>,
+  background: vec4<f32>,
+  background_scale: vec2<f32>,
+  background_offset: vec2<f32>,
+  mode: u32,
+  blur_background: u32,
+};
+
+const MODE_CLAMP: u32 = 0u;
+const MODE_LETTERBOX: u32 = 1u;
+const MODE_REPEAT: u32 = 2u;
+
+// Samples an image placed on the surface. Parts of the surface outside the image show
+// `background_view`, the blurred image, or the background color.
+fn sample_placed(
+  view: texture_2d<f32>,
+  view_sampler: sampler,
+  background_view: texture_2d<f32>,
+  background_sampler: sampler,
+  placement: Placement,
+  surface_coords: vec2<f32>,
+) -> vec4<f32> {
+  return sample_placed_grad(
+    view, view_sampler, background_view, background_sampler, placement, surface_coords,
+    dpdx(surface_coords), dpdy(surface_coords)
+  );
+}
+
+// Like `sample_placed`, with the gradients of `surface_coords` given explicitly for coordinates
+// that do not change smoothly across the surface or are computed in non-uniform control flow.
+fn sample_placed_grad(
+  view: texture_2d<f32>,
+  view_sampler: sampler,
+  background_view: texture_2d<f32>,
+  background_sampler: sampler,
+  placement: Placement,
+  surface_coords: vec2<f32>,
+  surface_ddx: vec2<f32>,
+  surface_ddy: vec2<f32>,
+) -> vec4<f32> {
+  let coords = surface_coords * placement.scale + placement.offset;
+  let background_coords = surface_coords * placement.background_scale + placement.background_offset;
+  // Explicit gradients so that wrapping coordinates does not cause seams
+  let ddx = surface_ddx * placement.scale;
+  let ddy = surface_ddy * placement.scale;
+  if (placement.mode == MODE_REPEAT) {
+    return textureSampleGrad(view, view_sampler, fract(coords), ddx, ddy);
+  }
+  let color = textureSampleGrad(view, view_sampler, coords, ddx, ddy);
+  if (placement.mode == MODE_LETTERBOX && (any(coords < vec2<f32>(0.0)) || any(coords > vec2<f32>(1.0)))) {
+    if (placement.blur_background != 0u) {
+      return textureSampleLevel(background_view, background_sampler, background_coords, 0.0);
+    }
+    return placement.background;
+  }
+  return color;
+}
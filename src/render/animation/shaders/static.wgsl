@@ -1,5 +1,3 @@
-
-
 struct VertexInput {
   @location(0) position: vec3<f32>,
   @location(1) tex_coords: vec2<f32>,
@@ -23,18 +21,14 @@ fn vs_main(
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_background: texture_2d<f32>;
+@group(0) @binding(3)
+var s_background: sampler;
 @group(1) @binding(0)
-var<uniform> surface_to_image_arr: f32;
+var<uniform> placement: Placement;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-  var tex_coords: vec2<f32>;
-  if (surface_to_image_arr > 1.0) {
-    let scale = 1.0 / surface_to_image_arr;
-    tex_coords = in.tex_coords * vec2<f32>(1.0, scale) + vec2<f32>(0.0, 0.5 * (1.0 - scale));
-  } else {
-    let scale = surface_to_image_arr;
-    tex_coords = in.tex_coords * vec2<f32>(scale, 1.0) + vec2<f32>(0.5 * (1.0 - scale), 0.0);
-  }
-  return textureSample(t_diffuse, s_diffuse, tex_coords);
+  return sample_placed(t_diffuse, s_diffuse, t_background, s_background, placement, in.tex_coords);
 }
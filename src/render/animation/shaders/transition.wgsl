@@ -0,0 +1,73 @@
+// Shared by all transitions, appended to their shaders after `placement.wgsl` and `vertex.wgsl`.
+//
+// A transition defines `fn transition(uv: vec2<f32>) -> vec4<f32>` returning the color at
+// surface coordinates `uv`, which run from (0, 0) at the top left to (1, 1) at the bottom right.
+// It samples the images with `from_color` and `to_color` and reads the state of the transition
+// from `params`.
+//
+// Custom transitions are `.wgsl` files following this contract in the `transitions` directory
+// next to the config file, used by their file name: `transitions/ripple.wgsl` is selected with
+// `type = "ripple"`. They can use everything declared here and in `placement.wgsl`, but must not
+// declare any of these names themselves. Transitions from gl-transitions.com can be used as
+// `.glsl` files instead, see `gl_transition.glsl`.
+
+struct TransitionParams {
+  // Eased progress from 0 to 1
+  progress: f32,
+  // Width of the surface divided by its height
+  aspect: f32,
+  // Seconds since the transition started
+  time: f32,
+  // Random value in [0, 1) chosen for each transition
+  seed: f32,
+  // Unit vector of the direction the transition moves in
+  direction: vec2<f32>,
+  placement_from: Placement,
+  placement_to: Placement,
+};
+
+@group(1) @binding(0)
+var<uniform> params: TransitionParams;
+
+@group(0) @binding(0)
+var from_view: texture_2d<f32>;
+@group(0) @binding(1)
+var from_sampler: sampler;
+@group(0) @binding(2)
+var from_background_view: texture_2d<f32>;
+@group(0) @binding(3)
+var from_background_sampler: sampler;
+@group(0) @binding(4)
+var to_view: texture_2d<f32>;
+@group(0) @binding(5)
+var to_sampler: sampler;
+@group(0) @binding(6)
+var to_background_view: texture_2d<f32>;
+@group(0) @binding(7)
+var to_background_sampler: sampler;
+
+// Gradients of the surface coordinates, taken in uniform control flow so that transitions can
+// sample anywhere
+var<private> surface_ddx: vec2<f32>;
+var<private> surface_ddy: vec2<f32>;
+
+fn from_color(uv: vec2<f32>) -> vec4<f32> {
+  return sample_placed_grad(
+    from_view, from_sampler, from_background_view, from_background_sampler,
+    params.placement_from, uv, surface_ddx, surface_ddy
+  );
+}
+
+fn to_color(uv: vec2<f32>) -> vec4<f32> {
+  return sample_placed_grad(
+    to_view, to_sampler, to_background_view, to_background_sampler,
+    params.placement_to, uv, surface_ddx, surface_ddy
+  );
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  surface_ddx = dpdx(in.tex_coords);
+  surface_ddy = dpdy(in.tex_coords);
+  return vec4<f32>(transition(in.tex_coords).rgb, 1.0);
+}
@@ -0,0 +1,9 @@
+const SMOOTHNESS: f32 = 0.05;
+
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  // Distance from the center, 1 in the corners
+  let aspect = vec2<f32>(params.aspect, 1.0);
+  let distance = length((uv - 0.5) * aspect) / length(0.5 * aspect);
+  let mask = smoothstep(0.0, SMOOTHNESS, params.progress * (1.0 + SMOOTHNESS) - distance);
+  return mix(from_color(uv), to_color(uv), mask);
+}
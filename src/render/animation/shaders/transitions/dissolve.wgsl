@@ -0,0 +1,14 @@
+// Number of noise cells along the height of the surface
+const CELLS: f32 = 120.0;
+const SMOOTHNESS: f32 = 0.1;
+
+fn hash(p: vec2<f32>) -> f32 {
+  return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  let cell = floor(uv * vec2<f32>(params.aspect, 1.0) * CELLS);
+  let noise = hash(cell + params.seed * 1000.0);
+  let mask = smoothstep(0.0, SMOOTHNESS, params.progress * (1.0 + SMOOTHNESS) - noise);
+  return mix(from_color(uv), to_color(uv), mask);
+}
@@ -0,0 +1,11 @@
+// Size of the pixels halfway through, relative to the height of the surface
+const MAX_PIXEL_SIZE: f32 = 0.05;
+
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  // Pixels grow until the middle of the transition, then shrink again
+  let strength = 1.0 - abs(params.progress * 2.0 - 1.0);
+  let size = max(strength * MAX_PIXEL_SIZE, 0.0001) / vec2<f32>(params.aspect, 1.0);
+  let pixel_uv = (floor(uv / size) + 0.5) * size;
+  let mask = smoothstep(0.4, 0.6, params.progress);
+  return mix(from_color(pixel_uv), to_color(pixel_uv), mask);
+}
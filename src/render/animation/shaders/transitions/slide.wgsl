@@ -0,0 +1,9 @@
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  // The new image pushes the old one out of the surface
+  let from_uv = uv - params.direction * params.progress;
+  let to_uv = from_uv + params.direction;
+  let from_sample = from_color(from_uv);
+  let to_sample = to_color(to_uv);
+  let outside = any(from_uv < vec2<f32>(0.0)) || any(from_uv > vec2<f32>(1.0));
+  return select(from_sample, to_sample, outside);
+}
@@ -0,0 +1,9 @@
+// Width of the soft edge
+const SMOOTHNESS: f32 = 0.05;
+
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  // Distance along the direction, 0 where the edge starts and 1 where it ends
+  let distance = dot(uv - 0.5, params.direction) + 0.5;
+  let mask = smoothstep(0.0, SMOOTHNESS, params.progress * (1.0 + SMOOTHNESS) - distance);
+  return mix(from_color(uv), to_color(uv), mask);
+}
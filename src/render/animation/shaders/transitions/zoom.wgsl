@@ -0,0 +1,7 @@
+// How far the old image is zoomed in at the end
+const ZOOM: f32 = 0.5;
+
+fn transition(uv: vec2<f32>) -> vec4<f32> {
+  let from_uv = (uv - 0.5) / (1.0 + params.progress * ZOOM) + 0.5;
+  return mix(from_color(from_uv), to_color(uv), params.progress);
+}
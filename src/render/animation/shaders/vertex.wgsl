@@ -0,0 +1,20 @@
+// Vertex stage of all transitions, also used with the fragment stage of GLSL transitions
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) tex_coords: vec2<f32>,
+};
+struct VertexOutput {
+  @builtin(position) clip_position: vec4<f32>,
+  @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+  model: VertexInput,
+) -> VertexOutput {
+  var out: VertexOutput;
+  out.clip_position = vec4<f32>(model.position, 1.0);
+  out.tex_coords = model.tex_coords;
+  return out;
+}
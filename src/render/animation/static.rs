@@ -1,21 +1,21 @@
-use std::{iter::once, path::Path};
+use std::{iter::once, time::Instant};
 
-use crate::render::{animation::INDICES, Context, Texture};
-use anyhow::Result;
+use crate::render::{animation::INDICES, Context, Framing, Placement, Scaling, Texture};
 use image::DynamicImage;
 use log::*;
 
 use super::{
-    create_index_buffer, create_pipeline, create_texture_binds, create_uniform_binds,
-    create_vertex_buffer, Animation,
+    create_index_buffer, create_pipeline, create_placed_texture_binds, create_shader,
+    create_uniform_binds, Animation,
 };
 
 pub struct Static {
     finished: bool,
+    scaling: Scaling,
+    framing: Framing,
     texture: Texture,
     texture_bind_group: wgpu::BindGroup,
 
-    vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
 
     uniform_buffer: wgpu::Buffer,
@@ -25,39 +25,35 @@ pub struct Static {
 }
 
 impl Static {
-    pub fn open(img: &Path, ctx: &Context) -> Result<Self> {
-        let img = image::open(img)?;
-        Ok(Self::from_img(&img, ctx))
-    }
-
-    pub fn from_img(img: &DynamicImage, ctx: &Context) -> Self {
-        let texture = Texture::from_image(img, ctx);
+    pub fn from_img(img: &DynamicImage, scaling: Scaling, ctx: &Context) -> Self {
+        let mut texture = Texture::from_image(img, ctx);
+        if scaling.needs_blur() {
+            texture.ensure_blurred(ctx);
+        }
 
         let (texture_bind_group_layout, texture_bind_group) =
-            create_texture_binds(&[&texture], ctx);
+            create_placed_texture_binds(&[&texture], ctx);
 
-        let vertex_buffer = create_vertex_buffer(ctx);
         let index_buffer = create_index_buffer(ctx);
 
         let (uniform_buffer, uniform_bind_group_layout, uniform_bind_group) =
-            create_uniform_binds(32, ctx);
+            create_uniform_binds(std::mem::size_of::<Placement>() as u64, ctx);
 
-        let shader = ctx
-            .device()
-            .create_shader_module(wgpu::include_wgsl!("./shaders/static.wgsl"));
+        let shader = create_shader(ctx, include_str!("./shaders/static.wgsl"));
 
         let render_pipeline = create_pipeline(
             ctx,
             &[&texture_bind_group_layout, &uniform_bind_group_layout],
             &shader,
-            ctx.config(),
+            ctx.config().format,
         );
 
         Self {
             finished: false,
+            scaling,
+            framing: Framing::default(),
             texture,
             texture_bind_group,
-            vertex_buffer,
             index_buffer,
             uniform_buffer,
             uniform_bind_group,
@@ -70,14 +66,30 @@ impl Animation for Static {
     fn is_finished(&self) -> bool {
         self.finished
     }
+    fn set_scaling(&mut self, scaling: Scaling, ctx: &Context) {
+        self.scaling = scaling;
+        self.finished = false;
+        if scaling.needs_blur() {
+            self.texture.ensure_blurred(ctx);
+        }
+        let (_, bindgroup) = create_placed_texture_binds(&[&self.texture], ctx);
+        self.texture_bind_group = bindgroup;
+    }
+    fn set_framing(&mut self, _from: Framing, to: Framing) {
+        self.finished &= self.framing == to;
+        self.framing = to;
+    }
     fn update_img(&mut self, img: &DynamicImage, ctx: &Context) {
         self.finished = false;
-        let texture = Texture::from_image(img, ctx);
+        let mut texture = Texture::from_image(img, ctx);
+        if self.scaling.needs_blur() {
+            texture.ensure_blurred(ctx);
+        }
         self.texture = texture;
-        let (_, bindgroup) = create_texture_binds(&[&self.texture], ctx);
+        let (_, bindgroup) = create_placed_texture_binds(&[&self.texture], ctx);
         self.texture_bind_group = bindgroup;
     }
-    fn render(&mut self, ctx: &Context) {
+    fn render(&mut self, ctx: &Context, _time: Instant) -> bool {
         let queue = ctx.queue();
         let device = ctx.device();
         let surface = ctx.surface();
@@ -85,7 +97,7 @@ impl Animation for Static {
         let output = surface.get_current_texture();
         if let Err(e) = output {
             error!("Could not get texture from surface: {e}");
-            return;
+            return false;
         }
         let output = output.unwrap();
         let view = output.texture.create_view(&Default::default());
@@ -93,7 +105,12 @@ impl Animation for Static {
         queue.write_buffer(
             &self.uniform_buffer,
             0,
-            bytemuck::cast_slice(&[ctx.surface_aspect_ratio() / self.texture.aspect_ratio()]),
+            bytemuck::cast_slice(&[Placement::new(
+                self.scaling,
+                ctx.view_size(),
+                self.texture.size(),
+            )
+            .framed(self.framing)]),
         );
 
         let mut encoder = device.create_command_encoder(&Default::default());
@@ -114,7 +131,7 @@ impl Animation for Static {
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(0, ctx.vertex_buffer().slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
             render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
             render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
@@ -123,5 +140,6 @@ impl Animation for Static {
         queue.submit(once(encoder.finish()));
         output.present();
         self.finished = true;
+        true
     }
 }
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    iter::once,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use log::*;
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    config::TransitionKind,
+    render::{self, texture, Context, Framing, Placement, Scaling, Texture},
+};
+
+use super::{
+    create_index_buffer, create_pipeline, create_placed_texture_binds, create_split_pipeline,
+    create_uniform_binds, create_vertex_buffer, Animation, Easing, Shader, ShaderLibrary, INDICES,
+    VERTEX_WGSL,
+};
+
+/// Directions transitions move in, one is picked at random for each transition.
+const DIRECTIONS: [[f32; 2]; 4] = [[1.0, 0.0], [-1.0, 0.0], [0.0, 1.0], [0.0, -1.0]];
+
+/// Blends from one image to the next with a shader from the [`ShaderLibrary`].
+pub struct Transition {
+    /// Whether an image was transitioned to yet, until then the image the transition was created
+    /// with is shown as is
+    running: bool,
+    /// Whether the frame changed outside of a transition, e.g. because the scaling changed
+    redraw: bool,
+    start_time: Option<Instant>,
+    duration: Duration,
+    easing: Easing,
+    scaling: Scaling,
+    /// The configured kind, which may be [`TransitionKind::Random`]
+    kind: TransitionKind,
+    /// The kind of the running transition
+    current: TransitionKind,
+    library: Rc<ShaderLibrary>,
+    direction: [f32; 2],
+    seed: f32,
+    /// Framing of the image transitioned from and the one transitioned to
+    framing: [Framing; 2],
+    /// Whether the image transitioned from is a snapshot of an interrupted transition, which is
+    /// already framed
+    from_snapshot: bool,
+
+    texture_a: Texture,
+    texture_b: Texture,
+    texture_bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+
+    /// Vertices drawing without a transform, for snapshots
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+
+    /// Pipelines of the kinds used so far
+    pipelines: HashMap<TransitionKind, wgpu::RenderPipeline>,
+}
+
+/// Matches `TransitionParams` in `transition.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniform {
+    progress: f32,
+    aspect: f32,
+    time: f32,
+    seed: f32,
+    direction: [f32; 2],
+    _padding: [f32; 2],
+    placement_from: Placement,
+    placement_to: Placement,
+}
+
+impl Transition {
+    /// Shows `img` until transitioning to the next image with [`update_img`](Self::update_img).
+    pub fn new(
+        kind: TransitionKind,
+        library: Rc<ShaderLibrary>,
+        img: &DynamicImage,
+        duration: Duration,
+        easing: Easing,
+        scaling: Scaling,
+        ctx: &render::Context,
+    ) -> Self {
+        // Nothing is transitioned from yet, the first transition replaces it with `img`
+        let mut texture_a = Texture::render_target((1, 1), texture::FORMAT, ctx);
+        let mut texture_b = Texture::from_image(img, ctx);
+        if scaling.needs_blur() {
+            texture_a.ensure_blurred(ctx);
+            texture_b.ensure_blurred(ctx);
+        }
+
+        let (texture_bind_group_layout, texture_bind_group) =
+            create_placed_texture_binds(&[&texture_a, &texture_b], ctx);
+
+        let vertex_buffer = create_vertex_buffer(ctx);
+        let index_buffer = create_index_buffer(ctx);
+
+        let (uniform_buffer, uniform_bind_group_layout, uniform_bind_group) =
+            create_uniform_binds(std::mem::size_of::<Uniform>() as u64, ctx);
+
+        let mut transition = Self {
+            running: false,
+            redraw: true,
+            start_time: None,
+            duration,
+            easing,
+            scaling,
+            current: kind.clone(),
+            kind,
+            library,
+            direction: DIRECTIONS[0],
+            seed: 0.0,
+            framing: [Framing::default(); 2],
+            from_snapshot: false,
+
+            texture_a,
+            texture_b,
+            texture_bind_group,
+            texture_bind_group_layout,
+
+            vertex_buffer,
+            index_buffer,
+
+            uniform_buffer,
+            uniform_bind_group,
+            uniform_bind_group_layout,
+
+            pipelines: HashMap::new(),
+        };
+        transition.pick(ctx);
+        transition
+    }
+
+    /// Chooses the kind, direction and seed of the next transition and compiles its shader if
+    /// it was not used before. Falls back to a fade if the shader cannot be used.
+    fn pick(&mut self, ctx: &Context) {
+        let mut rng = rand::thread_rng();
+        self.current = match &self.kind {
+            TransitionKind::Random => self
+                .library
+                .pool()
+                .choose(&mut rng)
+                .cloned()
+                .unwrap_or(TransitionKind::Fade),
+            kind => kind.clone(),
+        };
+        self.direction = *DIRECTIONS.choose(&mut rng).unwrap();
+        self.seed = rng.gen();
+        for kind in [self.current.clone(), TransitionKind::Fade] {
+            if self.pipelines.contains_key(&kind) {
+                self.current = kind;
+                return;
+            }
+            if let Some(pipeline) = self.create_pipeline(&kind, ctx) {
+                self.pipelines.insert(kind.clone(), pipeline);
+                self.current = kind;
+                return;
+            }
+            warn!(
+                "Could not use transition {}, falling back to fade",
+                kind.name()
+            );
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        kind: &TransitionKind,
+        ctx: &Context,
+    ) -> Option<wgpu::RenderPipeline> {
+        let shader = self.library.shader(kind)?;
+        let device = ctx.device();
+        let layouts = [
+            &self.texture_bind_group_layout,
+            &self.uniform_bind_group_layout,
+        ];
+        let format = ctx.config().format;
+        // Custom shaders are validated when loading them, but may still not fit the pipeline
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = match shader {
+            Shader::Wgsl(source) => {
+                let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(kind.name()),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                create_pipeline(ctx, &layouts, &shader, format)
+            }
+            Shader::Glsl(source) => {
+                let vertex = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: None,
+                    source: wgpu::ShaderSource::Wgsl(VERTEX_WGSL.into()),
+                });
+                let fragment = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(kind.name()),
+                    source: wgpu::ShaderSource::Glsl {
+                        shader: source.into(),
+                        stage: wgpu::naga::ShaderStage::Fragment,
+                        defines: Default::default(),
+                    },
+                });
+                create_split_pipeline(ctx, &layouts, &vertex, (&fragment, "main"), format)
+            }
+        };
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            error!(
+                "Failed to create pipeline for transition {}: {e}",
+                kind.name()
+            );
+            return None;
+        }
+        Some(pipeline)
+    }
+
+    /// Renders the frame currently on screen into a texture if a transition is in progress, so
+    /// that the next one can start from it instead of jumping back to the previous image.
+    fn snapshot(&mut self, ctx: &Context) -> Option<Texture> {
+        let running = self.running && self.start_time.is_some_and(|t| t.elapsed() < self.duration);
+        if !running || !self.pipelines.contains_key(&self.current) {
+            return None;
+        }
+        // Drawn with the pipelines of the surface, so it needs the surface format. Having the size
+        // of the surface, it is placed as is with every scale mode. It is drawn untransformed, as
+        // images are.
+        let snapshot = Texture::render_target(ctx.view_size(), ctx.config().format, ctx);
+        self.update_uniform(ctx, Instant::now());
+        self.draw(snapshot.view(), &self.vertex_buffer, ctx);
+        Some(snapshot)
+    }
+
+    fn duration_secs(&self) -> f32 {
+        // Avoid dividing by zero for instant transitions
+        self.duration.as_secs_f32().max(f32::EPSILON)
+    }
+
+    /// Writes the uniforms of the frame shown at `time`, starting the transition with the first
+    /// frame.
+    fn update_uniform(&mut self, ctx: &Context, time: Instant) {
+        let start_time = *self.start_time.get_or_insert(time);
+        let time = time.saturating_duration_since(start_time).as_secs_f32();
+        let linear = time / self.duration_secs();
+        let progress = if !self.running || linear >= 1.0 {
+            1.0
+        } else {
+            self.easing.ease(linear)
+        };
+
+        debug!("progress = {progress}");
+
+        let surface_size = ctx.view_size();
+        let from_framing = if self.from_snapshot {
+            Framing::default()
+        } else {
+            self.framing[0]
+        };
+        let data = Uniform {
+            progress,
+            aspect: surface_size.0 as f32 / surface_size.1.max(1) as f32,
+            time,
+            seed: self.seed,
+            direction: self.direction,
+            _padding: [0.0; 2],
+            placement_from: Placement::new(self.scaling, surface_size, self.texture_a.size())
+                .framed(from_framing),
+            placement_to: Placement::new(self.scaling, surface_size, self.texture_b.size())
+                .framed(self.framing[1]),
+        };
+        ctx.queue()
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[data]));
+    }
+
+    /// Draws the transition with the current uniforms to `view` with `vertex_buffer`.
+    fn draw(&self, view: &wgpu::TextureView, vertex_buffer: &wgpu::Buffer, ctx: &Context) {
+        let mut encoder = ctx.device().create_command_encoder(&Default::default());
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_pipeline(&self.pipelines[&self.current]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            render_pass.set_bind_group(0, &self.texture_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+            render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+        }
+        ctx.queue().submit(once(encoder.finish()));
+    }
+}
+
+impl Animation for Transition {
+    fn is_finished(&self) -> bool {
+        if !self.pipelines.contains_key(&self.current) {
+            // Nothing can be drawn
+            return true;
+        }
+        if self.redraw {
+            return false;
+        }
+        if !self.running {
+            // Only a still image is shown
+            return true;
+        }
+        self.start_time
+            .map(|x| x.elapsed().as_secs_f32() / self.duration_secs() > 1.1)
+            .unwrap_or(false)
+    }
+    fn set_timing(&mut self, duration: Duration, easing: Easing) {
+        self.duration = duration;
+        self.easing = easing;
+    }
+
+    fn set_framing(&mut self, from: Framing, to: Framing) {
+        self.framing = [from, to];
+    }
+
+    fn set_scaling(&mut self, scaling: Scaling, ctx: &Context) {
+        self.scaling = scaling;
+        self.redraw = true;
+        if scaling.needs_blur() {
+            self.texture_a.ensure_blurred(ctx);
+            self.texture_b.ensure_blurred(ctx);
+        }
+        let (_, bindgroup) = create_placed_texture_binds(&[&self.texture_a, &self.texture_b], ctx);
+        self.texture_bind_group = bindgroup;
+    }
+
+    fn update_img(&mut self, img: &DynamicImage, ctx: &Context) {
+        let snapshot = self.snapshot(ctx);
+        self.running = true;
+        self.start_time = None;
+        let mut texture = Texture::from_image(img, ctx);
+        if self.scaling.needs_blur() {
+            texture.ensure_blurred(ctx);
+        }
+        let previous = std::mem::replace(&mut self.texture_b, texture);
+        self.from_snapshot = snapshot.is_some();
+        self.texture_a = snapshot.unwrap_or(previous);
+        let (_, bindgroup) = create_placed_texture_binds(&[&self.texture_a, &self.texture_b], ctx);
+        self.texture_bind_group = bindgroup;
+        self.pick(ctx);
+    }
+
+    fn render(&mut self, ctx: &Context, time: Instant) -> bool {
+        if !self.pipelines.contains_key(&self.current) {
+            // Not even the fallback could be compiled
+            return false;
+        }
+        let surface = ctx.surface();
+
+        let output = surface.get_current_texture();
+        if let Err(e) = output {
+            error!("Could not get texture from surface: {e}");
+            return false;
+        }
+        let output = output.unwrap();
+        let view = output.texture.create_view(&Default::default());
+
+        self.update_uniform(ctx, time);
+        self.draw(&view, ctx.vertex_buffer(), ctx);
+        output.present();
+        self.redraw = false;
+        true
+    }
+}
@@ -0,0 +1,115 @@
+use std::iter::once;
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    animation::{
+        create_index_buffer, create_pipeline, create_texture_bind_group,
+        create_texture_bind_group_layout, create_uniform_bind_group,
+        create_uniform_bind_group_layout, create_vertex_buffer, INDICES,
+    },
+    texture, Context, Texture,
+};
+
+/// Longest side of the texture the blur runs on. Blurring at a low resolution is cheap and the
+/// result is upscaled smoothly by the sampler.
+const BLUR_SIZE: u32 = 256;
+/// Number of horizontal and vertical blur passes
+const BLUR_PASSES: usize = 2;
+
+/// Pipeline of a single blur or downscaling pass, created once per GPU, see
+/// [`Gpu::blur_pipeline`](super::Gpu::blur_pipeline).
+pub struct Pipeline {
+    texture_layout: wgpu::BindGroupLayout,
+    uniform_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+
+impl Pipeline {
+    pub fn new(ctx: &Context) -> Self {
+        let shader = ctx
+            .device()
+            .create_shader_module(wgpu::include_wgsl!("./animation/shaders/blur.wgsl"));
+        let texture_layout = create_texture_bind_group_layout(1, ctx);
+        let uniform_layout = create_uniform_bind_group_layout(ctx);
+        let pipeline = create_pipeline(
+            ctx,
+            &[&texture_layout, &uniform_layout],
+            &shader,
+            texture::FORMAT,
+        );
+        Self {
+            texture_layout,
+            uniform_layout,
+            pipeline,
+            vertex_buffer: create_vertex_buffer(ctx),
+            index_buffer: create_index_buffer(ctx),
+        }
+    }
+}
+
+/// Creates a heavily blurred, downscaled copy of `texture`.
+pub fn blur(texture: &Texture, ctx: &Context) -> Texture {
+    let device = ctx.device();
+    let Pipeline {
+        texture_layout,
+        uniform_layout,
+        pipeline,
+        vertex_buffer,
+        index_buffer,
+    } = ctx.gpu().blur_pipeline(ctx);
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    let mut pass = |source: &Texture, size: (u32, u32), step: [f32; 2]| {
+        let target = Texture::render_target(size, texture::FORMAT, ctx);
+        let texture_bind_group = create_texture_bind_group(texture_layout, &[source], ctx);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&step),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let uniform_bind_group = create_uniform_bind_group(uniform_layout, &uniform_buffer, ctx);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(0, &texture_bind_group, &[]);
+        render_pass.set_bind_group(1, &uniform_bind_group, &[]);
+        render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+        drop(render_pass);
+        target
+    };
+
+    // Halve the size until it is close to BLUR_SIZE, each step averaging 2x2 pixels
+    let mut size = texture.size();
+    let mut current = None;
+    while size.0.max(size.1) > BLUR_SIZE * 2 {
+        size = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+        let source = current.as_ref().unwrap_or(texture);
+        current = Some(pass(source, size, [0.0, 0.0]));
+    }
+    let mut current = current.unwrap_or_else(|| pass(texture, size, [0.0, 0.0]));
+    for _ in 0..BLUR_PASSES {
+        current = pass(&current, size, [1.0 / size.0 as f32, 0.0]);
+        current = pass(&current, size, [0.0, 1.0 / size.1 as f32]);
+    }
+
+    ctx.queue().submit(once(encoder.finish()));
+    current
+}
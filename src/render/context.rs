@@ -1,73 +1,122 @@
-use std::{ffi::c_void, ptr::NonNull};
+use std::{cell::OnceCell, ffi::c_void, ptr::NonNull, rc::Rc};
 
+use anyhow::{Context as _, Result};
 use client::Connection;
 use client::Proxy;
 use raw_window_handle::{
     RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle,
 };
-use smithay_client_toolkit::reexports::client;
+use smithay_client_toolkit::reexports::client::{self, protocol::wl_output};
 use smithay_client_toolkit::shell::{wlr_layer::LayerSurface, WaylandSurface};
+use wgpu::util::DeviceExt;
 
-pub struct Context {
-    surface: wgpu::Surface<'static>,
+use super::{animation::transformed_vertices, blur, mipmap, transform};
+
+/// GPU state shared by the surfaces of every output.
+pub struct Gpu {
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    config: wgpu::SurfaceConfiguration,
+    /// Created on first use and shared by every surface
+    mipmap: OnceCell<mipmap::Pipeline>,
+    blur: OnceCell<blur::Pipeline>,
 }
 
-impl Context {
-    pub async fn new(conn: &Connection, layer: &LayerSurface, size: (u32, u32)) -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
-            ..Default::default()
-        });
-
-        let raw_layer_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
-            NonNull::new(layer.wl_surface().id().as_ptr() as *mut c_void).unwrap(),
-        ));
-        let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
-            NonNull::new(conn.backend().display_ptr() as *mut c_void).unwrap(),
-        ));
-
-        let surface = unsafe {
-            instance
-                .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
-                    raw_window_handle: raw_layer_handle,
-                    raw_display_handle,
-                })
-                .expect("Failed to create gpu surface")
-        };
+impl Gpu {
+    async fn new(
+        instance: wgpu::Instance,
+        compatible_surface: &wgpu::Surface<'static>,
+    ) -> Result<Self> {
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                compatible_surface: Some(&surface),
+                compatible_surface: Some(compatible_surface),
                 force_fallback_adapter: false,
                 ..Default::default()
             })
             .await
-            .expect("Failed to get adapter");
+            .context("No GPU adapter available")?;
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
                     required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits {
-                        max_texture_dimension_2d: 16384,
-                        ..Default::default()
-                    },
+                    // Allow textures as large as the adapter supports
+                    required_limits: wgpu::Limits::default().using_resolution(adapter.limits()),
                 },
                 None,
             )
             .await
-            .expect("Failed to get device");
+            .context("Failed to get device")?;
+
+        Ok(Self {
+            instance,
+            adapter,
+            device,
+            queue,
+            mipmap: OnceCell::new(),
+            blur: OnceCell::new(),
+        })
+    }
+
+    /// Pipeline generating mipmaps, see [`mipmap::generate`].
+    pub fn mipmap_pipeline(&self, ctx: &Context) -> &mipmap::Pipeline {
+        self.mipmap.get_or_init(|| mipmap::Pipeline::new(ctx))
+    }
+
+    /// Pipeline blurring backgrounds, see [`blur::blur`].
+    pub fn blur_pipeline(&self, ctx: &Context) -> &blur::Pipeline {
+        self.blur.get_or_init(|| blur::Pipeline::new(ctx))
+    }
+
+    /// Largest width or height of a texture.
+    pub fn max_texture_dimension(&self) -> u32 {
+        self.device.limits().max_texture_dimension_2d
+    }
+}
+
+pub struct Context {
+    surface: wgpu::Surface<'static>,
+    gpu: Rc<Gpu>,
+    config: wgpu::SurfaceConfiguration,
+    /// Transform of the buffer relative to the surface
+    transform: wl_output::Transform,
+    /// Vertices drawing to the surface with `transform`
+    vertex_buffer: wgpu::Buffer,
+}
+
+impl Context {
+    /// Creates a context rendering to `layer`. The GPU device of an existing context can be
+    /// passed in as `gpu` to share it, otherwise a new one is created, which fails if there is
+    /// no usable GPU.
+    pub async fn new(
+        conn: &Connection,
+        layer: &LayerSurface,
+        size: (u32, u32),
+        gpu: Option<Rc<Gpu>>,
+    ) -> Result<Self> {
+        let (surface, gpu) = match gpu {
+            Some(gpu) => (create_surface(&gpu.instance, conn, layer)?, gpu),
+            None => {
+                let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+                    backends: wgpu::Backends::PRIMARY,
+                    ..Default::default()
+                });
+                let surface = create_surface(&instance, conn, layer)?;
+                let gpu = Gpu::new(instance, &surface).await?;
+                (surface, Rc::new(gpu))
+            }
+        };
 
-        let surface_caps = surface.get_capabilities(&adapter);
+        let surface_caps = surface.get_capabilities(&gpu.adapter);
         let surface_format = surface_caps
             .formats
             .iter()
             .find(|f| f.is_srgb())
+            .or(surface_caps.formats.first())
             .copied()
-            .unwrap_or(surface_caps.formats[0]);
+            .context("Surface is not supported by the GPU")?;
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -79,39 +128,96 @@ impl Context {
             view_formats: Vec::new(),
             desired_maximum_frame_latency: 2,
         };
-        surface.configure(&device, &config);
-        Self {
-            device,
-            queue,
+        surface.configure(&gpu.device, &config);
+        let transform = wl_output::Transform::Normal;
+        let vertex_buffer = gpu
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&transformed_vertices(transform)),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+        Ok(Self {
             surface,
+            gpu,
             config,
-        }
+            transform,
+            vertex_buffer,
+        })
     }
 
-    pub fn resize(&mut self, dimensions: (u32, u32)) {
+    /// Resizes the buffer to `dimensions` pixels, drawing it with `transform`.
+    pub fn resize(&mut self, dimensions: (u32, u32), transform: wl_output::Transform) {
         let (width, height) = dimensions;
         self.config.width = width;
         self.config.height = height;
-        self.surface.configure(&self.device, &self.config);
+        self.surface.configure(&self.gpu.device, &self.config);
+        if transform != self.transform {
+            self.transform = transform;
+            self.gpu.queue.write_buffer(
+                &self.vertex_buffer,
+                0,
+                bytemuck::cast_slice(&transformed_vertices(transform)),
+            );
+        }
+    }
+
+    /// Size of the buffer in pixels.
+    pub fn surface_size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    /// Size of the surface in pixels, which images are placed against. Width and height are
+    /// swapped compared to the buffer if it is rotated.
+    pub fn view_size(&self) -> (u32, u32) {
+        transform::transformed_size(self.surface_size(), self.transform)
     }
 
-    pub fn surface_aspect_ratio(&self) -> f32 {
-        self.config.width as f32 / self.config.height as f32
+    /// Vertices of a quad covering the surface, see
+    /// [`transformed_vertices`](super::animation::transformed_vertices).
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
     }
 
     pub fn surface(&self) -> &wgpu::Surface<'static> {
         &self.surface
     }
 
+    pub fn gpu(&self) -> &Rc<Gpu> {
+        &self.gpu
+    }
+
     pub fn device(&self) -> &wgpu::Device {
-        &self.device
+        &self.gpu.device
     }
 
     pub fn queue(&self) -> &wgpu::Queue {
-        &self.queue
+        &self.gpu.queue
     }
 
     pub fn config(&self) -> &wgpu::SurfaceConfiguration {
         &self.config
     }
 }
+
+fn create_surface(
+    instance: &wgpu::Instance,
+    conn: &Connection,
+    layer: &LayerSurface,
+) -> Result<wgpu::Surface<'static>> {
+    let raw_layer_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
+        NonNull::new(layer.wl_surface().id().as_ptr() as *mut c_void).unwrap(),
+    ));
+    let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+        NonNull::new(conn.backend().display_ptr() as *mut c_void).unwrap(),
+    ));
+
+    unsafe {
+        instance
+            .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+                raw_window_handle: raw_layer_handle,
+                raw_display_handle,
+            })
+            .context("Failed to create gpu surface")
+    }
+}
@@ -0,0 +1,124 @@
+use std::iter::once;
+
+use super::{
+    animation::{create_index_buffer, create_pipeline, create_vertex_buffer, INDICES},
+    texture, Context,
+};
+
+/// Number of mip levels of a full chain for a texture of `size`, down to 1x1.
+pub fn level_count(size: (u32, u32)) -> u32 {
+    32 - size.0.max(size.1).max(1).leading_zeros()
+}
+
+/// Pipeline halving a mip level into the next, created once per GPU, see
+/// [`Gpu::mipmap_pipeline`](super::Gpu::mipmap_pipeline).
+pub struct Pipeline {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+
+impl Pipeline {
+    pub fn new(ctx: &Context) -> Self {
+        let device = ctx.device();
+        let shader =
+            device.create_shader_module(wgpu::include_wgsl!("./animation/shaders/mipmap.wgsl"));
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline = create_pipeline(ctx, &[&layout], &shader, texture::FORMAT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Self {
+            layout,
+            pipeline,
+            sampler,
+            vertex_buffer: create_vertex_buffer(ctx),
+            index_buffer: create_index_buffer(ctx),
+        }
+    }
+}
+
+/// Fills all mip levels of `texture` after the first by halving the previous level, which
+/// needs the texture to be usable as a render attachment.
+pub fn generate(texture: &wgpu::Texture, ctx: &Context) {
+    let device = ctx.device();
+    let Pipeline {
+        layout,
+        pipeline,
+        sampler,
+        vertex_buffer,
+        index_buffer,
+    } = ctx.gpu().mipmap_pipeline(ctx);
+
+    let level_view = |level| {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })
+    };
+    let mut encoder = device.create_command_encoder(&Default::default());
+    for level in 1..texture.mip_level_count() {
+        let source = level_view(level - 1);
+        let target = level_view(level);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..1);
+    }
+    ctx.queue().submit(once(encoder.finish()));
+}
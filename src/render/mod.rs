@@ -1,7 +1,16 @@
 pub mod animation;
+mod blur;
 pub mod context;
+mod mipmap;
+pub mod placement;
+pub mod renderer;
+pub mod software;
 pub mod texture;
+pub mod transform;
 
 pub use animation::Animation;
-pub use context::Context;
+pub use context::{Context, Gpu};
+pub use placement::{Framing, Placement, Scaling};
+pub use renderer::{GpuRenderer, Renderer};
+pub use software::SoftwareRenderer;
 pub use texture::Texture;
@@ -0,0 +1,278 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+/// How an image is scaled to cover a surface of a different size.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMode {
+    /// Scale to cover the whole surface, cropping the overflow
+    #[default]
+    Fill,
+    /// Scale to fit inside the surface, filling the bars with the background
+    Fit,
+    /// Scale to the size of the surface, ignoring the aspect ratio
+    Stretch,
+    /// Show unscaled in the middle of the surface
+    Center,
+    /// Repeat unscaled across the surface
+    Tile,
+}
+
+/// An sRGB color, written as `#rrggbb` or `#rgb`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl Color {
+    /// The color in linear space, as expected by shaders writing to an sRGB surface.
+    pub fn to_linear(self) -> [f32; 4] {
+        let linear = |c: f32| {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        [linear(self.r), linear(self.g), linear(self.b), 1.0]
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        let hex = s
+            .strip_prefix('#')
+            .with_context(|| format!("Color {s} does not start with #"))?;
+        let digits: Vec<_> = match hex.len() {
+            3 => hex.chars().map(|c| c.to_string().repeat(2)).collect(),
+            6 => (0..6)
+                .step_by(2)
+                .map(|i| hex[i..i + 2].to_string())
+                .collect(),
+            _ => bail!("Color {s} is not of the form #rrggbb or #rgb"),
+        };
+        let channels = digits
+            .iter()
+            .map(|d| u8::from_str_radix(d, 16).map(|c| c as f32 / 255.0))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Color {s} is not a valid hex color"))?;
+        Ok(Self {
+            r: channels[0],
+            g: channels[1],
+            b: channels[2],
+        })
+    }
+}
+
+/// What fills the parts of the surface not covered by the image, written as a color or `blur`.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Background {
+    Color(Color),
+    /// A blurred and zoomed copy of the image scaled to cover the surface
+    Blur,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self::Color(Color::default())
+    }
+}
+
+impl TryFrom<String> for Background {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        if s == "blur" {
+            return Ok(Self::Blur);
+        }
+        Color::try_from(s)
+            .map(Self::Color)
+            .context("Background must be a color or \"blur\"")
+    }
+}
+
+/// Scale mode and background an image is drawn with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scaling {
+    pub mode: ScaleMode,
+    pub background: Background,
+}
+
+impl Scaling {
+    /// Whether textures need a blurred copy to be drawn with these settings.
+    pub fn needs_blur(&self) -> bool {
+        self.background == Background::Blur
+            && matches!(self.mode, ScaleMode::Fit | ScaleMode::Center)
+    }
+
+    /// Size an image of `image_size` pixels is shown at on a surface of `surface_size` pixels,
+    /// or `None` if it is shown at full size somewhere.
+    pub fn shown_size(
+        &self,
+        surface_size: (u32, u32),
+        image_size: (u32, u32),
+    ) -> Option<(u32, u32)> {
+        let surface = [surface_size.0 as f32, surface_size.1 as f32];
+        let image = [image_size.0 as f32, image_size.1 as f32];
+        let scale = match self.mode {
+            ScaleMode::Fill => (surface[0] / image[0]).max(surface[1] / image[1]),
+            ScaleMode::Fit => (surface[0] / image[0]).min(surface[1] / image[1]),
+            ScaleMode::Stretch => {
+                let width = surface_size.0.min(image_size.0);
+                let height = surface_size.1.min(image_size.1);
+                return ((width, height) != image_size).then_some((width, height));
+            }
+            ScaleMode::Center | ScaleMode::Tile => return None,
+        };
+        if scale >= 1.0 {
+            return None;
+        }
+        let scaled = |size: f32| ((size * scale).ceil() as u32).max(1);
+        Some((scaled(image[0]), scaled(image[1])))
+    }
+}
+
+const MODE_CLAMP: u32 = 0;
+const MODE_LETTERBOX: u32 = 1;
+const MODE_REPEAT: u32 = 2;
+
+/// How much the blurred background is zoomed in beyond covering the surface, hiding its
+/// darkened edges
+const BLUR_ZOOM: f32 = 1.1;
+
+/// Part of the placed image that is shown, zoomed by `zoom` into the point `focus` given in
+/// surface coordinates. Zooming never shows anything outside the part shown without zoom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Framing {
+    /// At least 1
+    pub zoom: f32,
+    pub focus: [f32; 2],
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            focus: [0.5, 0.5],
+        }
+    }
+}
+
+/// Maps surface texture coordinates onto an image, matching `Placement` in `placement.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Placement {
+    scale: [f32; 2],
+    offset: [f32; 2],
+    background: [f32; 4],
+    background_scale: [f32; 2],
+    background_offset: [f32; 2],
+    mode: u32,
+    blur_background: u32,
+    _padding: [u32; 2],
+}
+
+impl Placement {
+    /// Placement of an image of `image_size` pixels on a surface of `surface_size` pixels.
+    pub fn new(scaling: Scaling, surface_size: (u32, u32), image_size: (u32, u32)) -> Self {
+        let surface = [surface_size.0 as f32, surface_size.1 as f32];
+        let image = [image_size.0 as f32, image_size.1 as f32];
+        let surface_to_image_arr = (surface[0] / surface[1]) / (image[0] / image[1]);
+
+        let cover_scale = if surface_to_image_arr > 1.0 {
+            [1.0, 1.0 / surface_to_image_arr]
+        } else {
+            [surface_to_image_arr, 1.0]
+        };
+        let (scale, mode) = match scaling.mode {
+            ScaleMode::Stretch => ([1.0, 1.0], MODE_CLAMP),
+            ScaleMode::Fill => (cover_scale, MODE_CLAMP),
+            ScaleMode::Fit if surface_to_image_arr > 1.0 => {
+                ([surface_to_image_arr, 1.0], MODE_LETTERBOX)
+            }
+            ScaleMode::Fit => ([1.0, 1.0 / surface_to_image_arr], MODE_LETTERBOX),
+            ScaleMode::Center => (
+                [surface[0] / image[0], surface[1] / image[1]],
+                MODE_LETTERBOX,
+            ),
+            ScaleMode::Tile => ([surface[0] / image[0], surface[1] / image[1]], MODE_REPEAT),
+        };
+        let background_scale = cover_scale.map(|s| s / BLUR_ZOOM);
+        let background = match scaling.background {
+            Background::Color(color) => color.to_linear(),
+            Background::Blur => [0.0, 0.0, 0.0, 1.0],
+        };
+
+        Self {
+            scale,
+            offset: centered_offset(scale),
+            background,
+            background_scale,
+            background_offset: centered_offset(background_scale),
+            mode,
+            blur_background: scaling.needs_blur() as u32,
+            _padding: [0; 2],
+        }
+    }
+
+    /// Shows only the part of the image selected by `framing`.
+    pub fn framed(mut self, framing: Framing) -> Self {
+        for i in 0..2 {
+            self.offset[i] += self.scale[i] * framing.focus[i] * (1.0 - 1.0 / framing.zoom);
+            self.scale[i] /= framing.zoom;
+        }
+        self
+    }
+}
+
+/// Offset keeping coordinates scaled by `scale` centered on the surface.
+fn centered_offset(scale: [f32; 2]) -> [f32; 2] {
+    [0.5 - scale[0] / 2.0, 0.5 - scale[1] / 2.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shown_sizes() {
+        use ScaleMode::*;
+
+        let surface = (1920, 1080);
+        let cases = [
+            // Mode, image size, shown size
+            (Fill, (3840, 1440), Some((2880, 1080))),
+            (Fill, (1920, 1080), None),
+            (Fill, (1000, 500), None),
+            // Covering one side is not enough
+            (Fill, (3840, 1000), None),
+            (Fit, (3840, 1440), Some((1920, 720))),
+            (Fit, (1000, 4320), Some((250, 1080))),
+            (Fit, (1000, 500), None),
+            // Never scaled to nothing
+            (Fit, (10000, 1), Some((1920, 1))),
+            (Stretch, (3840, 720), Some((1920, 720))),
+            (Stretch, (3840, 2160), Some((1920, 1080))),
+            (Stretch, (1000, 500), None),
+            (Center, (3840, 2160), None),
+            (Tile, (3840, 2160), None),
+        ];
+        for (mode, image, expected) in cases {
+            let scaling = Scaling {
+                mode,
+                ..Scaling::default()
+            };
+            assert_eq!(
+                scaling.shown_size(surface, image),
+                expected,
+                "{mode:?} with {image:?}"
+            );
+        }
+    }
+}
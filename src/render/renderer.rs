@@ -0,0 +1,135 @@
+use std::{rc::Rc, time::Instant};
+
+use image::DynamicImage;
+use smithay_client_toolkit::reexports::client::protocol::wl_output;
+
+use crate::config::{OutputSettings, TransitionKind};
+
+use super::{
+    animation::{KenBurns, ShaderLibrary, Static, Transition},
+    Animation, Context, Gpu,
+};
+
+/// Draws the wallpaper of a single output.
+pub trait Renderer {
+    /// Changes the size of the buffer in pixels and the transform its contents are drawn with,
+    /// which is also passed to the compositor so that it does not need to rotate them.
+    fn resize(&mut self, size: (u32, u32), transform: wl_output::Transform);
+    /// Draws and presents the frame shown at `time`, returning whether the surface was committed.
+    fn draw(&mut self, time: Instant) -> bool;
+    /// Shows `img`, transitioning from the image shown so far.
+    fn update_img(&mut self, img: &DynamicImage);
+    /// Whether there are frames left to draw, either because the content changed or because an
+    /// animation is running.
+    fn is_animating(&self) -> bool;
+    /// Applies changed settings. The animation is rebuilt with `library` if `current_img`, the
+    /// image currently shown, is given, which is needed when the type of transition changed.
+    fn set_settings(
+        &mut self,
+        settings: &OutputSettings,
+        library: &Rc<ShaderLibrary>,
+        current_img: Option<&DynamicImage>,
+    );
+    /// Largest width or height of an image that can be shown without downscaling it.
+    fn max_image_dimension(&self) -> Option<u32> {
+        None
+    }
+    /// The GPU rendered with, to be shared with the renderers of other outputs.
+    fn gpu(&self) -> Option<&Rc<Gpu>> {
+        None
+    }
+}
+
+/// Renders with wgpu, see [`animation`](super::animation).
+pub struct GpuRenderer {
+    animation: Box<dyn Animation>,
+    // drop ctx after animation
+    ctx: Context,
+}
+
+impl GpuRenderer {
+    pub fn new(
+        ctx: Context,
+        settings: &OutputSettings,
+        library: &Rc<ShaderLibrary>,
+        img: &DynamicImage,
+    ) -> Self {
+        let animation = create_animation(settings, library, img, &ctx);
+        Self { animation, ctx }
+    }
+}
+
+impl Renderer for GpuRenderer {
+    fn resize(&mut self, size: (u32, u32), transform: wl_output::Transform) {
+        self.ctx.resize(size, transform);
+    }
+
+    fn draw(&mut self, time: Instant) -> bool {
+        self.animation.render(&self.ctx, time)
+    }
+
+    fn update_img(&mut self, img: &DynamicImage) {
+        self.animation.update_img(img, &self.ctx);
+    }
+
+    fn is_animating(&self) -> bool {
+        !self.animation.is_finished()
+    }
+
+    fn set_settings(
+        &mut self,
+        settings: &OutputSettings,
+        library: &Rc<ShaderLibrary>,
+        current_img: Option<&DynamicImage>,
+    ) {
+        let transition = &settings.transition;
+        match current_img {
+            Some(img) => {
+                self.animation = create_animation(settings, library, img, &self.ctx);
+            }
+            None => {
+                self.animation
+                    .set_timing(transition.duration(), transition.easing);
+                self.animation.set_scaling(settings.scaling, &self.ctx);
+            }
+        }
+    }
+
+    fn max_image_dimension(&self) -> Option<u32> {
+        Some(self.ctx.gpu().max_texture_dimension())
+    }
+
+    fn gpu(&self) -> Option<&Rc<Gpu>> {
+        Some(self.ctx.gpu())
+    }
+}
+
+/// Creates the animation for `settings`, showing `img` without a transition until the next image.
+fn create_animation(
+    settings: &OutputSettings,
+    library: &Rc<ShaderLibrary>,
+    img: &DynamicImage,
+    ctx: &Context,
+) -> Box<dyn Animation> {
+    let transition = &settings.transition;
+    let animation: Box<dyn Animation> = match &transition.kind {
+        TransitionKind::None => Box::new(Static::from_img(img, settings.scaling, ctx)),
+        kind => Box::new(Transition::new(
+            kind.clone(),
+            library.clone(),
+            img,
+            transition.duration(),
+            transition.easing,
+            settings.scaling,
+            ctx,
+        )),
+    };
+    if settings.ken_burns.enabled {
+        return Box::new(KenBurns::new(
+            animation,
+            settings.ken_burns,
+            settings.interval,
+        ));
+    }
+    animation
+}
@@ -0,0 +1,391 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+    time::Instant,
+};
+
+use anyhow::{Context as _, Result};
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView, Rgba, RgbaImage,
+};
+use log::*;
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use smithay_client_toolkit::{
+    reexports::{
+        calloop::ping::Ping,
+        client::protocol::{wl_output, wl_shm, wl_surface},
+    },
+    shm::{
+        slot::{Buffer, SlotPool},
+        Shm,
+    },
+};
+
+use crate::config::{OutputSettings, TransitionKind};
+
+use super::{
+    animation::ShaderLibrary,
+    placement::{Background, ScaleMode},
+    transform, Renderer, Scaling,
+};
+
+/// Longest side of the image the blurred background is made from, small as blurring on the CPU
+/// is slow
+const BLUR_SIZE: u32 = 64;
+const BLUR_SIGMA: f32 = 3.0;
+/// Bytes per pixel of [`wl_shm::Format::Xrgb8888`]
+const PIXEL_SIZE: usize = 4;
+
+/// Renders on the CPU into shared memory buffers, used when no GPU is available. Images are
+/// placed like on the GPU, but every transition is drawn as a fade and there is no Ken Burns
+/// effect.
+///
+/// Scaling and placing an image takes long for large outputs, so frames are composed on the
+/// worker pool. Until the frame for a new image or size is ready, the previous one stays.
+pub struct SoftwareRenderer {
+    surface: wl_surface::WlSurface,
+    pool: SlotPool,
+    buffer: Option<Buffer>,
+    size: (u32, u32),
+    transform: wl_output::Transform,
+    settings: OutputSettings,
+    /// The image shown or faded to
+    img: Arc<DynamicImage>,
+    /// Frames faded from and to, as little endian XRGB8888 pixels of `size`
+    from: Vec<u8>,
+    to: Vec<u8>,
+    fading: bool,
+    /// Set when the first frame of the fade is drawn
+    fade_start: Option<Instant>,
+    /// Whether the frame changed since it was last drawn
+    dirty: bool,
+    /// Number of the latest frame requested from the worker pool
+    generation: u64,
+    /// Frame composed on the worker pool and not shown yet
+    composed: Arc<Mutex<Option<Composed>>>,
+    /// Wakes up the event loop once a frame is composed
+    wake: Ping,
+}
+
+/// A frame composed on the worker pool, see [`SoftwareRenderer::spawn_compose`].
+struct Composed {
+    generation: u64,
+    /// Whether to fade to the frame, otherwise it replaces what is shown right away
+    fade: bool,
+    frame: Vec<u8>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(
+        shm: &Shm,
+        wake: Ping,
+        surface: wl_surface::WlSurface,
+        size: (u32, u32),
+        settings: &OutputSettings,
+        img: &DynamicImage,
+    ) -> Result<Self> {
+        let pool = SlotPool::new(size.0 as usize * size.1 as usize * PIXEL_SIZE, shm)
+            .context("Failed to create shared memory pool")?;
+        warn_unsupported(settings);
+        let mut renderer = Self {
+            surface,
+            pool,
+            buffer: None,
+            size,
+            transform: wl_output::Transform::Normal,
+            settings: settings.clone(),
+            img: Arc::new(img.clone()),
+            from: Vec::new(),
+            to: Vec::new(),
+            fading: false,
+            fade_start: None,
+            dirty: false,
+            generation: 0,
+            composed: Arc::default(),
+            wake,
+        };
+        renderer.spawn_compose(false);
+        Ok(renderer)
+    }
+
+    /// Composes the current image on the worker pool, superseding frames requested before. It is
+    /// faded to if `fade` is set, otherwise it replaces the frame shown and ends any fade.
+    fn spawn_compose(&mut self, fade: bool) {
+        self.generation += 1;
+        let generation = self.generation;
+        let img = self.img.clone();
+        let (scaling, size, transform) = (self.settings.scaling, self.size, self.transform);
+        let composed = self.composed.clone();
+        let wake = self.wake.clone();
+        rayon::spawn(move || {
+            let frame = compose(&img, scaling, size, transform);
+            let mut composed = lock(&composed);
+            // Frames requested later may be done first
+            if composed.as_ref().is_none_or(|c| c.generation < generation) {
+                *composed = Some(Composed {
+                    generation,
+                    fade,
+                    frame,
+                });
+                wake.ping();
+            }
+        });
+    }
+
+    /// Whether the latest frame requested is composed and waiting to be shown.
+    fn is_composed(&self) -> bool {
+        lock(&self.composed)
+            .as_ref()
+            .is_some_and(|c| c.generation == self.generation)
+    }
+
+    /// Shows the latest frame requested if it is composed.
+    fn take_composed(&mut self) {
+        let Some(composed) = lock(&self.composed).take() else {
+            return;
+        };
+        if composed.generation != self.generation {
+            return;
+        }
+        // Fading needs the previous frame at the same size
+        if composed.fade && self.to.len() == composed.frame.len() {
+            if self.fading {
+                // Fade from what is on screen instead of jumping back to the previous image
+                let progress = self.progress(Instant::now());
+                let mut frame = vec![0; self.to.len()];
+                blend(&self.from, &self.to, progress, &mut frame);
+                self.from = frame;
+            } else {
+                self.from = mem::take(&mut self.to);
+            }
+            self.fading = self.settings.transition.kind != TransitionKind::None;
+        } else {
+            self.from.clone_from(&composed.frame);
+            self.fading = false;
+        }
+        self.to = composed.frame;
+        self.fade_start = None;
+        self.dirty = true;
+    }
+
+    /// Eased progress of the fade at `time`, ending it once it is complete.
+    fn progress(&mut self, time: Instant) -> f32 {
+        if !self.fading {
+            return 1.0;
+        }
+        let transition = &self.settings.transition;
+        let start = *self.fade_start.get_or_insert(time);
+        let linear = time.saturating_duration_since(start).as_secs_f32()
+            / transition.duration.max(f32::EPSILON);
+        if linear >= 1.0 {
+            self.fading = false;
+            return 1.0;
+        }
+        transition.easing.ease(linear)
+    }
+
+    fn present(&mut self, progress: f32) -> Result<()> {
+        let (width, height) = (self.size.0 as i32, self.size.1 as i32);
+        let stride = width * PIXEL_SIZE as i32;
+        let buffer = match self.buffer.take() {
+            Some(buffer) if self.pool.canvas(&buffer).is_some() => buffer,
+            // The compositor still holds on to the previous buffer
+            _ => {
+                self.pool
+                    .create_buffer(width, height, stride, wl_shm::Format::Xrgb8888)
+                    .context("Failed to create buffer")?
+                    .0
+            }
+        };
+        let canvas = self.pool.canvas(&buffer).context("Buffer is in use")?;
+        blend(&self.from, &self.to, progress, canvas);
+        buffer
+            .attach_to(&self.surface)
+            .context("Failed to attach buffer")?;
+        self.surface.damage_buffer(0, 0, width, height);
+        self.surface.commit();
+        self.buffer = Some(buffer);
+        Ok(())
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn resize(&mut self, size: (u32, u32), transform: wl_output::Transform) {
+        if size == self.size && transform == self.transform {
+            return;
+        }
+        self.size = size;
+        self.transform = transform;
+        self.buffer = None;
+        // Frames of the previous size cannot be drawn anymore
+        self.fading = false;
+        self.dirty = false;
+        self.spawn_compose(false);
+    }
+
+    fn draw(&mut self, time: Instant) -> bool {
+        self.take_composed();
+        if self.to.len() != self.size.0 as usize * self.size.1 as usize * PIXEL_SIZE {
+            // Drawn again once the frame for the current size is composed
+            return false;
+        }
+        let progress = self.progress(time);
+        match self.present(progress) {
+            Ok(()) => {
+                self.dirty = false;
+                true
+            }
+            Err(e) => {
+                error!("Could not draw frame: {e:#}");
+                false
+            }
+        }
+    }
+
+    fn update_img(&mut self, img: &DynamicImage) {
+        self.img = Arc::new(img.clone());
+        self.spawn_compose(true);
+    }
+
+    fn is_animating(&self) -> bool {
+        self.fading || self.dirty || self.is_composed()
+    }
+
+    fn set_settings(
+        &mut self,
+        settings: &OutputSettings,
+        _library: &std::rc::Rc<ShaderLibrary>,
+        current_img: Option<&DynamicImage>,
+    ) {
+        warn_unsupported(settings);
+        let scaling_changed = settings.scaling != self.settings.scaling;
+        self.settings = settings.clone();
+        if let Some(img) = current_img {
+            self.img = Arc::new(img.clone());
+        }
+        if scaling_changed || current_img.is_some() {
+            self.spawn_compose(false);
+        }
+    }
+}
+
+fn warn_unsupported(settings: &OutputSettings) {
+    let kind = &settings.transition.kind;
+    if !matches!(kind, TransitionKind::None | TransitionKind::Fade) {
+        warn!(
+            "Transition {} is not supported by the software renderer, fading instead",
+            kind.name()
+        );
+    }
+    if settings.ken_burns.enabled {
+        warn!("Ken Burns effect is not supported by the software renderer");
+    }
+}
+
+/// Draws `img` as XRGB8888 pixels into a buffer of `size` with `transform`.
+fn compose(
+    img: &DynamicImage,
+    scaling: Scaling,
+    size: (u32, u32),
+    transform: wl_output::Transform,
+) -> Vec<u8> {
+    // Placed against the surface, which is rotated compared to the buffer
+    let (width, height) = transform::transformed_size((size.0.max(1), size.1.max(1)), transform);
+    let filter = FilterType::Triangle;
+    let mut frame = match scaling.background {
+        Background::Blur if scaling.needs_blur() => blurred_cover(img, (width, height)),
+        Background::Color(color) => {
+            let channel = |c: f32| (c * 255.0).round() as u8;
+            RgbaImage::from_pixel(
+                width,
+                height,
+                Rgba([channel(color.r), channel(color.g), channel(color.b), 255]),
+            )
+        }
+        Background::Blur => RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])),
+    };
+    let centered = |image: (u32, u32)| {
+        (
+            (width as i64 - image.0 as i64) / 2,
+            (height as i64 - image.1 as i64) / 2,
+        )
+    };
+    match scaling.mode {
+        ScaleMode::Fill => frame = img.resize_to_fill(width, height, filter).to_rgba8(),
+        ScaleMode::Stretch => frame = img.resize_exact(width, height, filter).to_rgba8(),
+        ScaleMode::Fit => {
+            let scaled = img.resize(width, height, filter);
+            let (x, y) = centered(scaled.dimensions());
+            imageops::overlay(&mut frame, &scaled.to_rgba8(), x, y);
+        }
+        ScaleMode::Center => {
+            let (x, y) = centered(img.dimensions());
+            imageops::overlay(&mut frame, &img.to_rgba8(), x, y);
+        }
+        ScaleMode::Tile => {
+            let tile = img.to_rgba8();
+            let (tile_width, tile_height) =
+                (tile.width().max(1) as i64, tile.height().max(1) as i64);
+            // Tiles are laid out around one in the center, as on the GPU
+            let (x, y) = centered(tile.dimensions());
+            for y in (y.rem_euclid(tile_height) - tile_height..height as i64)
+                .step_by(tile_height as usize)
+            {
+                for x in (x.rem_euclid(tile_width) - tile_width..width as i64)
+                    .step_by(tile_width as usize)
+                {
+                    imageops::overlay(&mut frame, &tile, x, y);
+                }
+            }
+        }
+    }
+    transform::transform_image(frame, transform)
+        .pixels()
+        .flat_map(|p| [p[2], p[1], p[0], 255])
+        .collect()
+}
+
+/// Blurred copy of `img` covering a surface of `size`.
+fn blurred_cover(img: &DynamicImage, size: (u32, u32)) -> RgbaImage {
+    let scale = BLUR_SIZE as f32 / size.0.max(size.1) as f32;
+    let small = (
+        ((size.0 as f32 * scale) as u32).max(1),
+        ((size.1 as f32 * scale) as u32).max(1),
+    );
+    let small = img.resize_to_fill(small.0, small.1, FilterType::Triangle);
+    let blurred = imageops::blur(&small, BLUR_SIGMA);
+    imageops::resize(&blurred, size.0, size.1, FilterType::Triangle)
+}
+
+/// Writes the mix of the frames `from` and `to` at `progress` into `out`.
+fn blend(from: &[u8], to: &[u8], progress: f32, out: &mut [u8]) {
+    const CHUNK: usize = 64 * 1024;
+    let weight = (progress.clamp(0.0, 1.0) * 256.0) as u32;
+    blend_pool().install(|| {
+        out.par_chunks_mut(CHUNK)
+            .zip(from.par_chunks(CHUNK))
+            .zip(to.par_chunks(CHUNK))
+            .for_each(|((out, from), to)| {
+                for ((out, &from), &to) in out.iter_mut().zip(from).zip(to) {
+                    *out = ((from as u32 * (256 - weight) + to as u32 * weight) >> 8) as u8;
+                }
+            });
+    });
+}
+
+/// Threads blending frames while the event loop waits for them. Separate from the global pool,
+/// so that images decoded and frames composed there do not hold up drawing.
+fn blend_pool() -> &'static ThreadPool {
+    static POOL: OnceLock<ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        ThreadPoolBuilder::new()
+            .thread_name(|i| format!("blend-{i}"))
+            .build()
+            .expect("Failed to start threads for blending frames")
+    })
+}
+
+fn lock(composed: &Mutex<Option<Composed>>) -> MutexGuard<'_, Option<Composed>> {
+    composed.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -1,26 +1,36 @@
-use std::path::Path;
+use image::{imageops::FilterType, GenericImageView};
+use log::*;
 
-use anyhow::Result;
-use image::GenericImageView;
+use super::{blur, mipmap, Context};
 
-use super::Context;
+/// Format of all textures, images are uploaded as sRGB.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 
 pub struct Texture {
     // texture: wgpu::Texture,
     size: wgpu::Extent3d,
     view: wgpu::TextureView,
     sampler: wgpu::Sampler,
+    blurred: Option<Box<Texture>>,
 }
 
 impl Texture {
-    pub fn open(path: &Path, ctx: &Context) -> Result<Self> {
-        let img = image::open(path)?;
-        Ok(Self::from_image(&img, ctx))
-    }
-
     pub fn from_image(img: &image::DynamicImage, ctx: &Context) -> Self {
         let device = ctx.device();
         let queue = ctx.queue();
+        let limit = ctx.gpu().max_texture_dimension();
+        let resized;
+        let img = if img.width() > limit || img.height() > limit {
+            warn!(
+                "{}x{} image exceeds the texture size limit of {limit}, downscaling",
+                img.width(),
+                img.height()
+            );
+            resized = img.resize(limit, limit, FilterType::Triangle);
+            &resized
+        } else {
+            img
+        };
         let rgba = img.to_rgba8();
         let (width, height) = img.dimensions();
 
@@ -33,11 +43,13 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size,
-            mip_level_count: 1,
+            mip_level_count: mipmap::level_count((width, height)),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
             view_formats: &[],
         });
 
@@ -56,23 +68,49 @@ impl Texture {
             },
             size,
         );
+        mipmap::generate(&texture, ctx);
+
+        Self::from_texture(&texture, ctx)
+    }
 
+    /// Creates an empty texture of `format` that can also be rendered to through its
+    /// [`view`](Self::view).
+    pub fn render_target(size: (u32, u32), format: wgpu::TextureFormat, ctx: &Context) -> Self {
+        let texture = ctx.device().create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Self::from_texture(&texture, ctx)
+    }
+
+    fn from_texture(texture: &wgpu::Texture, ctx: &Context) -> Self {
         let view = texture.create_view(&Default::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = ctx.device().create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
         Self {
             // texture,
-            size,
+            size: texture.size(),
             view,
             sampler,
+            blurred: None,
         }
     }
 
@@ -88,9 +126,19 @@ impl Texture {
         &self.sampler
     }
 
-    pub fn aspect_ratio(&self) -> f32 {
-        let width = self.size.width as f32;
-        let height = self.size.height as f32;
-        width / height
+    pub fn size(&self) -> (u32, u32) {
+        (self.size.width, self.size.height)
+    }
+
+    /// Blurred copy of the texture if it was created with
+    /// [`ensure_blurred`](Self::ensure_blurred), otherwise the texture itself.
+    pub fn blurred(&self) -> &Texture {
+        self.blurred.as_deref().unwrap_or(self)
+    }
+
+    pub fn ensure_blurred(&mut self, ctx: &Context) {
+        if self.blurred.is_none() {
+            self.blurred = Some(Box::new(blur::blur(self, ctx)));
+        }
     }
 }
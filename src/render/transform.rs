@@ -0,0 +1,118 @@
+use image::{imageops, RgbaImage};
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
+/// Whether `transform` rotates by 90 or 270 degrees, swapping width and height.
+pub fn swaps_axes(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// Size of a buffer with `transform` for a surface of `size`, or the other way around.
+pub fn transformed_size(size: (u32, u32), transform: Transform) -> (u32, u32) {
+    if swaps_axes(transform) {
+        (size.1, size.0)
+    } else {
+        size
+    }
+}
+
+/// Position on the surface shown at the normalized position `[x, y]` of a buffer with
+/// `transform`, y pointing down.
+pub fn surface_coords([x, y]: [f32; 2], transform: Transform) -> [f32; 2] {
+    match transform {
+        Transform::_90 => [1.0 - y, x],
+        Transform::_180 => [1.0 - x, 1.0 - y],
+        Transform::_270 => [y, 1.0 - x],
+        Transform::Flipped => [1.0 - x, y],
+        Transform::Flipped90 => [y, x],
+        Transform::Flipped180 => [x, 1.0 - y],
+        Transform::Flipped270 => [1.0 - y, 1.0 - x],
+        _ => [x, y],
+    }
+}
+
+/// Turns a frame in the orientation of the surface into the contents of a buffer with
+/// `transform`. Rotations are counter-clockwise and flipped transforms flip horizontally first.
+pub fn transform_image(frame: RgbaImage, transform: Transform) -> RgbaImage {
+    let frame = match transform {
+        Transform::Flipped
+        | Transform::Flipped90
+        | Transform::Flipped180
+        | Transform::Flipped270 => imageops::flip_horizontal(&frame),
+        _ => frame,
+    };
+    match transform {
+        Transform::_90 | Transform::Flipped90 => imageops::rotate270(&frame),
+        Transform::_180 | Transform::Flipped180 => imageops::rotate180(&frame),
+        Transform::_270 | Transform::Flipped270 => imageops::rotate90(&frame),
+        _ => frame,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const TRANSFORMS: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    #[test]
+    fn transformed_sizes() {
+        for transform in TRANSFORMS {
+            let expected = if swaps_axes(transform) {
+                (2, 3)
+            } else {
+                (3, 2)
+            };
+            assert_eq!(
+                transformed_size((3, 2), transform),
+                expected,
+                "{transform:?}"
+            );
+            assert_eq!(
+                transformed_size(expected, transform),
+                (3, 2),
+                "{transform:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn surface_coords_match_transformed_images() {
+        // Every pixel is different, so each one can be traced back to the surface
+        let frame = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        for transform in TRANSFORMS {
+            let buffer = transform_image(frame.clone(), transform);
+            assert_eq!(
+                buffer.dimensions(),
+                transformed_size(frame.dimensions(), transform),
+                "{transform:?}"
+            );
+            for (x, y, pixel) in buffer.enumerate_pixels() {
+                // Pixel centers, so rounding down finds the pixel on the surface
+                let center = [
+                    (x as f32 + 0.5) / buffer.width() as f32,
+                    (y as f32 + 0.5) / buffer.height() as f32,
+                ];
+                let [sx, sy] = surface_coords(center, transform);
+                let shown = frame.get_pixel(
+                    (sx * frame.width() as f32) as u32,
+                    (sy * frame.height() as f32) as u32,
+                );
+                assert_eq!(pixel, shown, "{transform:?} at {x}, {y}");
+            }
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use image::DynamicImage;
+use log::*;
+
+use smithay_client_toolkit::{
+    compositor::{CompositorState, Region},
+    output::OutputInfo,
+    reexports::{
+        calloop::{ping::Ping, RegistrationToken},
+        client::{protocol::wl_output, Connection, QueueHandle},
+        protocols::wp::{
+            fractional_scale::v1::client::{
+                wp_fractional_scale_manager_v1, wp_fractional_scale_v1,
+            },
+            presentation_time::client::wp_presentation,
+            viewporter::client::{wp_viewport, wp_viewporter},
+        },
+    },
+    shell::{
+        wlr_layer::{Anchor, LayerShell, LayerSurface},
+        WaylandSurface,
+    },
+    shm::Shm,
+};
+
+use crate::{
+    app::App,
+    config::{Config, OutputSettings},
+    frame_clock::FrameClock,
+    render::{self, animation::ShaderLibrary, transform, GpuRenderer, Renderer, SoftwareRenderer},
+};
+
+/// Size surfaces are created with until the compositor configures them.
+const INITIAL_SIZE: (u32, u32) = (256, 256);
+
+/// The layer surface and renderer showing the wallpaper of a single output.
+pub struct Wallpaper {
+    output: wl_output::WlOutput,
+    renderer: Box<dyn Renderer>,
+    // Layer needs to be dropped after the renderer
+    layer: LayerSurface,
+
+    settings: OutputSettings,
+    timer: RegistrationToken,
+    configured: bool,
+    /// Whether a frame callback was requested and has not arrived yet
+    frame_pending: bool,
+    presentation: Option<wp_presentation::WpPresentation>,
+    clock: FrameClock,
+    /// Size of the surface in logical coordinates, as configured by the compositor
+    size: (u32, u32),
+    scale: Scale,
+    /// Transform of the output, applied by the renderer
+    transform: wl_output::Transform,
+    /// Scales the buffer to the size of the surface when using fractional scales
+    viewport: Option<wp_viewport::WpViewport>,
+    fractional_scale: Option<wp_fractional_scale_v1::WpFractionalScaleV1>,
+    mode: Option<OutputMode>,
+    history: History,
+    prefetch: Prefetch,
+}
+
+/// The image decoded ahead of the next switch.
+pub enum Prefetch {
+    /// Nothing is being decoded
+    None,
+    /// Decoding on the worker pool, to be shown as soon as it is ready if `switch` is set
+    Loading {
+        switch: bool,
+    },
+    Ready(PathBuf, DynamicImage),
+}
+
+/// Optional protocols wallpapers use if the compositor supports them.
+pub struct Protocols {
+    pub presentation: Option<wp_presentation::WpPresentation>,
+    pub viewporter: Option<wp_viewporter::WpViewporter>,
+    pub fractional_scale: Option<wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1>,
+}
+
+/// How many physical pixels a buffer has per logical pixel of the surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scale {
+    /// Applied with `wl_surface.set_buffer_scale`
+    Integer(u32),
+    /// In 120ths as sent by `wp_fractional_scale_v1`, applied with a viewport
+    Fractional(u32),
+}
+
+impl Scale {
+    /// Size of the buffer for a surface of `size`.
+    fn buffer_size(self, size: (u32, u32)) -> (u32, u32) {
+        match self {
+            Self::Integer(factor) => (size.0 * factor, size.1 * factor),
+            // Rounded half away from zero, as the protocol asks
+            Self::Fractional(scale) => ((size.0 * scale + 60) / 120, (size.1 * scale + 60) / 120),
+        }
+    }
+}
+
+/// Logical size and current mode of an output, used to detect mode changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OutputMode {
+    logical_size: Option<(i32, i32)>,
+    dimensions: Option<(i32, i32)>,
+}
+
+impl Wallpaper {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        conn: &Connection,
+        qh: &QueueHandle<App>,
+        compositor_state: &CompositorState,
+        layer_shell: &LayerShell,
+        config: &Config,
+        settings: OutputSettings,
+        output: wl_output::WlOutput,
+        renderer: RendererChoice,
+        protocols: &Protocols,
+        library: &Rc<ShaderLibrary>,
+        img: (PathBuf, DynamicImage),
+        timer: RegistrationToken,
+    ) -> Result<Self> {
+        let surface = compositor_state.create_surface(qh);
+
+        let layer = layer_shell.create_layer_surface(
+            qh,
+            surface,
+            config.layer.into(),
+            Some(config.namespace.clone()),
+            Some(&output),
+        );
+        layer.set_anchor(Anchor::all());
+        layer.set_size(0, 0);
+        layer.set_exclusive_zone(-1);
+
+        match Region::new(compositor_state) {
+            Ok(region) => {
+                layer.set_input_region(Some(region.wl_region()));
+                region.wl_region().destroy();
+            }
+            Err(e) => {
+                warn!("Failed to set input region, background may not have cursor: {e}");
+            }
+        }
+
+        // Fractional scales can only be applied with a viewport
+        let (viewport, fractional_scale) =
+            match (&protocols.viewporter, &protocols.fractional_scale) {
+                (Some(viewporter), Some(manager)) => {
+                    let surface = layer.wl_surface();
+                    (
+                        Some(viewporter.get_viewport(surface, qh, ())),
+                        Some(manager.get_fractional_scale(surface, qh, surface.clone())),
+                    )
+                }
+                _ => (None, None),
+            };
+
+        layer.commit();
+
+        let (path, img) = img;
+        let renderer = create_renderer(conn, &layer, renderer, &settings, library, &img)?;
+        let mut history = History::default();
+        history.push(path);
+
+        Ok(Self {
+            output,
+            renderer,
+            layer,
+            settings,
+            timer,
+            configured: false,
+            frame_pending: false,
+            presentation: protocols.presentation.clone(),
+            clock: FrameClock::default(),
+            size: INITIAL_SIZE,
+            scale: Scale::Integer(1),
+            transform: wl_output::Transform::Normal,
+            viewport,
+            fractional_scale,
+            mode: None,
+            history,
+            prefetch: Prefetch::None,
+        })
+    }
+
+    pub fn output(&self) -> &wl_output::WlOutput {
+        &self.output
+    }
+
+    pub fn layer(&self) -> &LayerSurface {
+        &self.layer
+    }
+
+    /// The GPU shared with other wallpapers, `None` if rendering in software.
+    pub fn gpu(&self) -> Option<&Rc<render::Gpu>> {
+        self.renderer.gpu()
+    }
+
+    /// Largest width or height of an image the wallpaper can show without downscaling it.
+    pub fn max_image_dimension(&self) -> Option<u32> {
+        self.renderer.max_image_dimension()
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    pub fn prefetch(&self) -> &Prefetch {
+        &self.prefetch
+    }
+
+    pub fn set_prefetch(&mut self, prefetch: Prefetch) -> Prefetch {
+        std::mem::replace(&mut self.prefetch, prefetch)
+    }
+
+    pub fn settings(&self) -> &OutputSettings {
+        &self.settings
+    }
+
+    /// Applies changed settings, see [`Renderer::set_settings`].
+    pub fn set_settings(
+        &mut self,
+        settings: OutputSettings,
+        library: &Rc<ShaderLibrary>,
+        current_img: Option<&DynamicImage>,
+    ) {
+        self.renderer.set_settings(&settings, library, current_img);
+        self.settings = settings;
+    }
+
+    pub fn timer(&self) -> RegistrationToken {
+        self.timer
+    }
+
+    pub fn set_timer(&mut self, timer: RegistrationToken) {
+        self.timer = timer;
+    }
+
+    /// Whether the wallpaper has frames left to draw.
+    pub fn is_animating(&self) -> bool {
+        self.configured && self.renderer.is_animating()
+    }
+
+    /// Records the current mode of the output, asking the compositor for a new configure if it
+    /// changed. Returns whether the mode changed.
+    pub fn update_mode(&mut self, info: &OutputInfo) -> bool {
+        let mode = OutputMode {
+            logical_size: info.logical_size,
+            dimensions: info.modes.iter().find(|m| m.current).map(|m| m.dimensions),
+        };
+        if self.mode.replace(mode).is_none_or(|old| old == mode) {
+            return false;
+        }
+        self.layer.set_size(0, 0);
+        self.layer.commit();
+        true
+    }
+
+    pub fn configure(&mut self, size: (u32, u32), qh: &QueueHandle<App>) {
+        self.size = size;
+        self.configured = true;
+        self.update_buffer(qh);
+    }
+
+    /// Called when the integer scale of the outputs the surface is on changes. Ignored when the
+    /// compositor sends fractional scales instead.
+    pub fn set_scale_factor(&mut self, factor: i32, qh: &QueueHandle<App>) {
+        if self.fractional_scale.is_none() {
+            self.set_scale(Scale::Integer(factor.max(1) as u32), qh);
+        }
+    }
+
+    /// Called with the preferred scale of the surface in 120ths.
+    pub fn set_fractional_scale(&mut self, scale: u32, qh: &QueueHandle<App>) {
+        self.set_scale(Scale::Fractional(scale), qh);
+    }
+
+    fn set_scale(&mut self, scale: Scale, qh: &QueueHandle<App>) {
+        if scale == self.scale {
+            return;
+        }
+        debug!("Scale of output changed to {scale:?}");
+        self.scale = scale;
+        if self.configured {
+            self.update_buffer(qh);
+        }
+    }
+
+    /// Called when the transform of the outputs the surface is on changes.
+    pub fn set_transform(&mut self, transform: wl_output::Transform, qh: &QueueHandle<App>) {
+        if transform == self.transform {
+            return;
+        }
+        debug!("Transform of output changed to {transform:?}");
+        self.transform = transform;
+        if self.configured {
+            self.update_buffer(qh);
+        }
+    }
+
+    /// Sizes the buffer to the physical pixels covered by the surface, in the orientation of the
+    /// output, and draws it again.
+    fn update_buffer(&mut self, qh: &QueueHandle<App>) {
+        if self.layer.set_buffer_transform(self.transform).is_err() {
+            warn!("Compositor does not support buffer transforms, leaving rotation to it");
+            self.transform = wl_output::Transform::Normal;
+        }
+        match self.scale {
+            Scale::Integer(factor) => {
+                if self.layer.set_buffer_scale(factor).is_err() {
+                    warn!("Compositor does not support buffer scales, rendering at scale 1");
+                    self.scale = Scale::Integer(1);
+                }
+            }
+            // The destination of a viewport cannot be empty
+            Scale::Fractional(_) if self.size.0 == 0 || self.size.1 == 0 => {}
+            Scale::Fractional(_) => {
+                if let Some(viewport) = &self.viewport {
+                    viewport.set_destination(self.size.0 as i32, self.size.1 as i32);
+                }
+            }
+        }
+        let size = transform::transformed_size(self.scale.buffer_size(self.size), self.transform);
+        self.renderer.resize(size, self.transform);
+        self.render(qh);
+    }
+
+    /// Draws the next frame if anything changed and the compositor has shown the previous one.
+    /// Returns false if the frame could not be drawn and needs to be retried.
+    pub fn draw(&mut self, qh: &QueueHandle<App>) -> bool {
+        if self.is_animating() && !self.frame_pending {
+            return self.render(qh);
+        }
+        true
+    }
+
+    fn render(&mut self, qh: &QueueHandle<App>) -> bool {
+        let surface = self.layer.wl_surface();
+        // Committed along with the frame. The compositor holds the callback back while the
+        // surface is hidden, which pauses drawing.
+        if !self.frame_pending {
+            surface.frame(qh, surface.clone());
+            self.frame_pending = true;
+        }
+        if let Some(presentation) = &self.presentation {
+            presentation.feedback(surface, qh, surface.clone());
+        }
+        let committed = self.renderer.draw(self.clock.next_frame());
+        if !committed {
+            // The callback only arrives after a commit, waiting for it would stop drawing
+            self.frame_pending = false;
+        }
+        committed
+    }
+
+    /// Called when the compositor is ready for a new frame.
+    pub fn frame_done(&mut self) {
+        self.frame_pending = false;
+    }
+
+    /// Called when a frame was shown at `time` on an output refreshing every `refresh`.
+    pub fn presented(&mut self, time: Instant, refresh: Duration) {
+        self.clock.presented(time, refresh);
+    }
+
+    pub fn update_img(&mut self, img: &DynamicImage) {
+        self.renderer.update_img(img);
+    }
+
+    /// Shows a new image, adding it to the history.
+    pub fn push_img(&mut self, path: PathBuf, img: &DynamicImage) {
+        self.history.push(path);
+        self.update_img(img);
+    }
+}
+
+impl Drop for Wallpaper {
+    fn drop(&mut self) {
+        // Destroyed before the surface they belong to
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+    }
+}
+
+/// How a new wallpaper renders.
+pub enum RendererChoice<'a> {
+    /// On the GPU, sharing the given one if any, falling back to software if there is no GPU
+    Gpu(Option<Rc<render::Gpu>>, &'a Shm, &'a Ping),
+    /// In software, waking up the event loop with the ping once frames are composed
+    Software(&'a Shm, &'a Ping),
+}
+
+fn create_renderer(
+    conn: &Connection,
+    layer: &LayerSurface,
+    choice: RendererChoice,
+    settings: &OutputSettings,
+    library: &Rc<ShaderLibrary>,
+    img: &DynamicImage,
+) -> Result<Box<dyn Renderer>> {
+    let (shm, wake) = match choice {
+        RendererChoice::Gpu(gpu, shm, wake) => {
+            match pollster::block_on(render::Context::new(conn, layer, INITIAL_SIZE, gpu)) {
+                Ok(ctx) => return Ok(Box::new(GpuRenderer::new(ctx, settings, library, img))),
+                Err(e) => {
+                    warn!("Could not render on the GPU, falling back to software rendering: {e:#}");
+                    (shm, wake)
+                }
+            }
+        }
+        RendererChoice::Software(shm, wake) => (shm, wake),
+    };
+    let surface = layer.wl_surface().clone();
+    let renderer = SoftwareRenderer::new(shm, wake.clone(), surface, INITIAL_SIZE, settings, img)?;
+    Ok(Box::new(renderer))
+}
+
+/// Whether switching from settings `old` to `new` needs a different [`Animation`].
+pub fn needs_rebuild(old: &OutputSettings, new: &OutputSettings) -> bool {
+    old.transition.kind != new.transition.kind
+        || old.ken_burns != new.ken_burns
+        || (new.ken_burns.enabled && old.interval != new.interval)
+}
+
+const HISTORY_LEN: usize = 64;
+
+/// Images previously shown on an output, for going back and forth between them.
+#[derive(Default)]
+pub struct History {
+    entries: VecDeque<PathBuf>,
+    position: usize,
+}
+
+impl History {
+    /// Adds `path` after the current entry, discarding any entries that were ahead of it.
+    pub fn push(&mut self, path: PathBuf) {
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.position + 1);
+        }
+        self.entries.push_back(path);
+        if self.entries.len() > HISTORY_LEN {
+            self.entries.pop_front();
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.entries.get(self.position).map(PathBuf::as_path)
+    }
+
+    /// Entry `offset` steps away from the current one.
+    pub fn peek(&self, offset: isize) -> Option<&Path> {
+        self.position
+            .checked_add_signed(offset)
+            .and_then(|i| self.entries.get(i))
+            .map(PathBuf::as_path)
+    }
+
+    /// Moves `offset` steps away from the current entry if such an entry exists.
+    pub fn seek(&mut self, offset: isize) {
+        if self.peek(offset).is_some() {
+            self.position = self.position.wrapping_add_signed(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(paths: &[&str]) -> History {
+        let mut history = History::default();
+        for path in paths {
+            history.push(PathBuf::from(path));
+        }
+        history
+    }
+
+    #[test]
+    fn buffer_sizes() {
+        assert_eq!(Scale::Integer(1).buffer_size((1366, 768)), (1366, 768));
+        assert_eq!(Scale::Integer(2).buffer_size((1366, 768)), (2732, 1536));
+        assert_eq!(Scale::Fractional(120).buffer_size((1366, 768)), (1366, 768));
+        assert_eq!(
+            Scale::Fractional(180).buffer_size((1366, 768)),
+            (2049, 1152)
+        );
+        // 1707.5 by 960, halves round up
+        assert_eq!(Scale::Fractional(150).buffer_size((1366, 768)), (1708, 960));
+        // 1821.33 by 1024
+        assert_eq!(
+            Scale::Fractional(160).buffer_size((1366, 768)),
+            (1821, 1024)
+        );
+        // 1138.33 by 640
+        assert_eq!(Scale::Fractional(100).buffer_size((1366, 768)), (1138, 640));
+    }
+
+    #[test]
+    fn empty_history() {
+        let mut history = History::default();
+        assert_eq!(history.current(), None);
+        assert_eq!(history.peek(-1), None);
+        history.seek(-1);
+        assert_eq!(history.current(), None);
+    }
+
+    #[test]
+    fn peek_and_seek() {
+        let mut history = history(&["a", "b", "c"]);
+        assert_eq!(history.current(), Some(Path::new("c")));
+        assert_eq!(history.peek(-2), Some(Path::new("a")));
+        assert_eq!(history.peek(1), None);
+        assert_eq!(history.peek(-3), None);
+
+        history.seek(-2);
+        assert_eq!(history.current(), Some(Path::new("a")));
+        // Seeking past either end stays put
+        history.seek(-1);
+        assert_eq!(history.current(), Some(Path::new("a")));
+        history.seek(3);
+        assert_eq!(history.current(), Some(Path::new("a")));
+        history.seek(1);
+        assert_eq!(history.current(), Some(Path::new("b")));
+    }
+
+    #[test]
+    fn push_discards_entries_ahead() {
+        let mut history = history(&["a", "b", "c"]);
+        history.seek(-2);
+        history.push(PathBuf::from("d"));
+        assert_eq!(history.current(), Some(Path::new("d")));
+        assert_eq!(history.peek(-1), Some(Path::new("a")));
+        assert_eq!(history.peek(1), None);
+    }
+
+    #[test]
+    fn push_drops_oldest_entries() {
+        let mut history = History::default();
+        for i in 0..HISTORY_LEN + 2 {
+            history.push(PathBuf::from(i.to_string()));
+        }
+        let last = HISTORY_LEN as isize - 1;
+        assert_eq!(history.peek(-last), Some(Path::new("2")));
+        assert_eq!(history.peek(-last - 1), None);
+        assert_eq!(
+            history.current(),
+            Some(Path::new(&(HISTORY_LEN + 1).to_string()))
+        );
+    }
+}